@@ -8,6 +8,8 @@
 //
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use std::fmt;
+
 #[derive(Debug, PartialEq)]
 pub struct Program<'a> {
     pub commands: CompleteCommands<'a>,
@@ -95,12 +97,39 @@ impl<'a> Pipeline<'a> {
     }
 }
 
+impl<'a> fmt::Display for Pipeline<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (i, cmd) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", cmd)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct SimpleCommand<'a> {
     pub assign: Vec<&'a str>,
     pub cmd: Arg<'a>,
     pub args: Vec<Arg<'a>>,
-    //pub redirect: Vec<Redirect<'a>>,
+    pub redirect: Vec<Redirect<'a>>,
+}
+
+impl<'a> fmt::Display for SimpleCommand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut words: Vec<String> = self.assign.iter().map(|a| a.to_string()).collect();
+        if self.cmd != Arg::Arg("") {
+            words.push(self.cmd.to_string());
+        }
+        words.extend(self.args.iter().map(|a| a.to_string()));
+        words.extend(self.redirect.iter().map(|r| r.to_string()));
+        write!(f, "{}", words.join(" "))
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -109,34 +138,91 @@ pub enum Command<'a> {
     Compound,
 }
 
+impl<'a> fmt::Display for Command<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(s) => write!(f, "{}", s),
+            Command::Compound => write!(f, "..."),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Arg<'a> {
     Arg(&'a str),
 }
 
+impl<'a> fmt::Display for Arg<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::Arg(s) => write!(f, "{}", s),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum TermOp {
     Semi,
     Amp,
 }
 
-//pub struct Redirect<'a> {
-//    command: &'a Command<'a>,
-//    operator: RedirectionType,
-//    fname: Arg<'a>,
-//    fd: int,
-//    dupfd: int,
-//}
-
-//pub enum RedirectionType {
-//    TO,     // fd > fname
-//    CLOBBER,// fd >| fname
-//    FROM,   // fd < fname
-//    FROMTO, // fd <> fname
-//    APPEND, // fd >> fname
-//    TOFD,   // fd <& dupfd
-//    FROMFD, // fd >& dupfd
-//}
+#[derive(Debug, PartialEq)]
+pub struct Redirect<'a> {
+    pub fd: i32,
+    pub op: RedirectOp,
+    pub target: Arg<'a>,
+}
+
+impl<'a> Redirect<'a> {
+    /// Creates a redirection of the given file descriptor
+    /// or of the operator's default descriptor if none is given.
+    pub fn new(fd: Option<&str>, op: RedirectOp, target: &'a str) -> Redirect<'a> {
+        let default_fd = match op {
+            RedirectOp::From | RedirectOp::FromTo | RedirectOp::FromFd => 0,
+            _ => 1,
+        };
+        Redirect {
+            fd: fd.and_then(|n| n.parse().ok()).unwrap_or(default_fd),
+            op,
+            target: Arg::Arg(target),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Redirect<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if Redirect::new(None, self.op, "").fd != self.fd {
+            write!(f, "{}", self.fd)?;
+        }
+        write!(f, "{}{}", self.op, self.target)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RedirectOp {
+    To,      // fd > fname
+    Clobber, // fd >| fname
+    From,    // fd < fname
+    FromTo,  // fd <> fname
+    Append,  // fd >> fname
+    ToFd,    // fd >& dupfd
+    FromFd,  // fd <& dupfd
+}
+
+impl fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            RedirectOp::To => ">",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::From => "<",
+            RedirectOp::FromTo => "<>",
+            RedirectOp::Append => ">>",
+            RedirectOp::ToFd => ">&",
+            RedirectOp::FromFd => "<&",
+        };
+        write!(f, "{}", op)
+    }
+}
 
 #[cfg(test)]
 #[allow(unused_imports)]
@@ -148,32 +234,34 @@ mod tests {
 
     use super::*;
 
-    fn try_parse(input: &str) -> Result<ast::Program, ParseError<usize, Tok, LexError>> {
+    fn try_parse(
+        input: &str,
+    ) -> Result<ast::Program<'_>, ParseError<usize, Tok<'_>, LexError<'_>>> {
         let parser = grammar::programParser::new();
         let lexer = lexer::Lexer::new(input);
-        return parser.parse(input, lexer);
+        parser.parse(input, lexer)
     }
 
     fn parse(input: &str) -> ast::Program<'_> {
-        return try_parse(input).unwrap();
+        try_parse(input).unwrap()
     }
 
     fn complete_command<'a>(program: &'a ast::Program) -> &'a Vec<(TermOp, AndOr<'a>)> {
-        return &program.commands.complete_commands[0].and_ors;
+        &program.commands.complete_commands[0].and_ors
     }
 
     fn single_command<'a>(program: &'a ast::Program, index: usize) -> &'a SimpleCommand<'a> {
         let command = &complete_command(program)[index].1.pipelines[0].1.commands[0];
-        return match command {
+        match command {
             Command::Simple(s) => s,
             Command::Compound => {
                 panic!("unimplemented");
             }
-        };
+        }
     }
 
     fn first_command<'a>(program: &'a ast::Program) -> &'a SimpleCommand<'a> {
-        return single_command(program, 0);
+        single_command(program, 0)
     }
 
     #[test]
@@ -190,12 +278,16 @@ mod tests {
             let program = parse(input);
             let command = complete_command(&program);
             assert_eq!(command.len(), 2);
-            let SimpleCommand { assign, cmd, args } = first_command(&program);
+            let SimpleCommand {
+                assign, cmd, args, ..
+            } = first_command(&program);
             assert_eq!(assign.len(), 0);
             assert_eq!(cmd, &Arg::Arg("echo"));
             assert_eq!(args[0], Arg::Arg("foo"));
 
-            let SimpleCommand { assign, cmd, args } = single_command(&program, 1);
+            let SimpleCommand {
+                assign, cmd, args, ..
+            } = single_command(&program, 1);
             assert_eq!(assign.len(), 0);
             assert_eq!(cmd, &Arg::Arg("bar"));
             assert_eq!(args.len(), 0);
@@ -215,6 +307,7 @@ mod tests {
             args,
             assign: _,
             cmd,
+            ..
         } = first_command(&program);
         assert_eq!(cmd, &Arg::Arg("echo"));
         assert_eq!(args.len(), 0);
@@ -224,6 +317,7 @@ mod tests {
             args,
             assign: _,
             cmd: _,
+            ..
         } = first_command(&program);
         assert_eq!(args.len(), 1);
         assert_eq!(args[0], Arg::Arg("foo"));
@@ -234,6 +328,7 @@ mod tests {
             args,
             assign: _,
             cmd: _,
+            ..
         } = first_command(&program);
         assert_eq!(args.len(), 1);
         assert_eq!(args[0], Arg::Arg("\"foo\""));
@@ -243,6 +338,7 @@ mod tests {
             args,
             assign: _,
             cmd: _,
+            ..
         } = first_command(&program);
         assert_eq!(args.len(), 1);
         assert_eq!(args[0], Arg::Arg("'foo'"));
@@ -253,6 +349,7 @@ mod tests {
             args,
             assign: _,
             cmd: _,
+            ..
         } = first_command(&program);
         assert_eq!(args.len(), 1);
         assert_eq!(args[0], Arg::Arg("\"foo\"bar"));
@@ -262,6 +359,7 @@ mod tests {
             args,
             assign: _,
             cmd: _,
+            ..
         } = first_command(&program);
         assert_eq!(args.len(), 2);
         assert_eq!(args[0], Arg::Arg("\"foo\""));
@@ -278,6 +376,7 @@ mod tests {
                 assign: vec!["FOO=BAR"],
                 cmd: Arg::Arg("echo"),
                 args: vec![],
+                redirect: vec![],
             }
         );
         // value can be empty
@@ -288,6 +387,7 @@ mod tests {
                 assign: vec!["FOO="],
                 cmd: Arg::Arg("echo"),
                 args: vec![],
+                redirect: vec![],
             }
         );
         // variable name can't start with a number
@@ -298,6 +398,7 @@ mod tests {
                 assign: vec![],
                 cmd: Arg::Arg("999FOO=BAR"),
                 args: vec![Arg::Arg("echo")],
+                redirect: vec![],
             }
         );
         // but it can contain one
@@ -308,6 +409,7 @@ mod tests {
                 assign: vec!["F9=BAR"],
                 cmd: Arg::Arg("echo"),
                 args: vec![],
+                redirect: vec![],
             }
         );
 
@@ -319,6 +421,7 @@ mod tests {
                 assign: vec!["a=1", "b=2", "c=3"],
                 cmd: Arg::Arg("echo"),
                 args: vec![],
+                redirect: vec![],
             }
         );
     }
@@ -334,10 +437,131 @@ mod tests {
                 assign: _assign,
                 cmd: Arg::Arg(cmd),
                 args: _args,
+                ..
             }) = &commands[0]
             {
                 assert_eq!(cmd, &"foo");
             }
         }
     }
+
+    #[test]
+    fn redirections() {
+        let program = parse("echo foo >out 2>>err <in");
+        let SimpleCommand { args, redirect, .. } = first_command(&program);
+        assert_eq!(args, &vec![Arg::Arg("foo")]);
+        assert_eq!(
+            redirect,
+            &vec![
+                Redirect::new(None, RedirectOp::To, "out"),
+                Redirect::new(Some("2"), RedirectOp::Append, "err"),
+                Redirect::new(None, RedirectOp::From, "in"),
+            ]
+        );
+        assert_eq!(redirect[0].fd, 1);
+        assert_eq!(redirect[1].fd, 2);
+        assert_eq!(redirect[2].fd, 0);
+
+        // redirections may come before the command and between assignments
+        let program = parse("A=1 >|out B=2 cmd 2>&1");
+        assert_eq!(
+            first_command(&program),
+            &SimpleCommand {
+                assign: vec!["A=1", "B=2"],
+                cmd: Arg::Arg("cmd"),
+                args: vec![],
+                redirect: vec![
+                    Redirect::new(None, RedirectOp::Clobber, "out"),
+                    Redirect::new(Some("2"), RedirectOp::ToFd, "1"),
+                ],
+            }
+        );
+
+        // a number only names a descriptor when it touches the operator
+        let program = parse("echo 2 >out");
+        let SimpleCommand { args, redirect, .. } = first_command(&program);
+        assert_eq!(args, &vec![Arg::Arg("2")]);
+        assert_eq!(redirect[0].fd, 1);
+
+        assert!(try_parse("echo >").is_err());
+    }
+
+    #[test]
+    fn newline_delimiter() {
+        let program = parse("echo foo\nA=1 bar\n\nbaz\n");
+        let commands = &program.commands.complete_commands;
+        assert_eq!(commands.len(), 3);
+
+        // every command may start with assignments
+        let program = parse("A=1 foo; B=2 bar\nC=3 baz");
+        assert_eq!(single_command(&program, 1).assign, vec!["B=2"]);
+        let command = &program.commands.complete_commands[1].and_ors[0].1.pipelines[0].1;
+        assert_eq!(command.commands[0].to_string(), "C=3 baz");
+
+        // quoted and escaped newlines are part of a word
+        let program = parse("echo 'foo\nbar' a\\\nb");
+        let SimpleCommand { args, .. } = first_command(&program);
+        assert_eq!(args, &vec![Arg::Arg("'foo\nbar'"), Arg::Arg("a\\\nb")]);
+    }
+
+    #[test]
+    fn comments() {
+        let program = parse("# leading comment\necho foo # trailing\n#last");
+        assert_eq!(program.commands.complete_commands.len(), 1);
+        let SimpleCommand { args, .. } = first_command(&program);
+        assert_eq!(args, &vec![Arg::Arg("foo")]);
+
+        // # only starts a comment at the beginning of a word
+        let program = parse("echo a#b '#c';#d");
+        let SimpleCommand { args, .. } = first_command(&program);
+        assert_eq!(args, &vec![Arg::Arg("a#b"), Arg::Arg("'#c'")]);
+    }
+
+    #[test]
+    fn bang_argument() {
+        // ! negates a pipeline at its start but is an ordinary word after the command name
+        let program = parse("! test ! -z x");
+        let pipeline = &program.commands.complete_commands[0].and_ors[0].1.pipelines[0].1;
+        assert!(pipeline.negated);
+        let SimpleCommand { args, .. } = first_command(&program);
+        assert_eq!(args, &vec![Arg::Arg("!"), Arg::Arg("-z"), Arg::Arg("x")]);
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = [
+            ("ll", "ls -l"),
+            ("loop", "loop x"),
+            ("n", "nice "),
+            ("v", "-v"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let parse = |input| {
+            let parser = grammar::programParser::new();
+            parser
+                .parse(input, lexer::Lexer::with_aliases(input, &aliases))
+                .unwrap()
+        };
+
+        let program = parse("ll v; echo ll");
+        let SimpleCommand { cmd, args, .. } = single_command(&program, 0);
+        assert_eq!(cmd, &Arg::Arg("ls"));
+        assert_eq!(args, &vec![Arg::Arg("-l"), Arg::Arg("v")]);
+        let SimpleCommand { args, .. } = single_command(&program, 1);
+        assert_eq!(args, &vec![Arg::Arg("ll")]);
+
+        // an alias isn't substituted within itself
+        let program = parse("loop");
+        let SimpleCommand { cmd, args, .. } = first_command(&program);
+        assert_eq!(cmd, &Arg::Arg("loop"));
+        assert_eq!(args, &vec![Arg::Arg("x")]);
+
+        // a trailing blank makes the next word eligible
+        let program = parse("n ll");
+        let SimpleCommand { cmd, args, .. } = first_command(&program);
+        assert_eq!(cmd, &Arg::Arg("nice"));
+        assert_eq!(args, &vec![Arg::Arg("ls"), Arg::Arg("-l")]);
+    }
 }
@@ -10,76 +10,408 @@
 
 use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
 use nix::errno::Errno;
+use nix::unistd::{access, AccessFlags};
 
+use crate::context;
 use crate::context::Context;
-use crate::context::StdIo;
+use crate::context::FdTable;
+use crate::eval::Eval;
+use crate::exec;
+use crate::input;
+use crate::jobs;
+use crate::lexer;
+use crate::printf;
+use crate::resource;
+use crate::test;
+use crate::trap;
 
-pub type Command = fn(&[String], &mut Context, StdIo) -> i32;
+pub type Command = fn(&[String], &mut Context, &FdTable) -> i32;
+/// a builtin which runs shell commands itself and so needs the evaluator
+pub type EvalCommand = fn(&[String], &mut Eval, &FdTable) -> i32;
+
+#[derive(Copy, Clone)]
+pub enum Builtin {
+    Command(Command),
+    Eval(EvalCommand),
+}
+
+impl Builtin {
+    /// Runs the builtin. If its output couldn't be written, the error is reported
+    /// and the builtin fails.
+    pub fn run(self, args: &[String], eval: &mut Eval, stdio: &FdTable) -> i32 {
+        let ret = match self {
+            Builtin::Command(c) => c(args, &mut eval.context, stdio),
+            Builtin::Eval(c) => c(args, eval, stdio),
+        };
+        match stdio.take_write_error() {
+            Some(e) => {
+                stdio.eprintln(format_args!("rash: {}: write error: {}", args[0], e.desc()));
+                1
+            }
+            None => ret,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Builtins {
-    commands: HashMap<String, Command>,
+    commands: HashMap<String, Builtin>,
 }
 
 impl Builtins {
     pub fn new() -> Builtins {
         let mut b: Builtins = Default::default();
+        b.insert_eval(".", dot);
+        b.insert("[", test::bracket);
+        b.insert("alias", alias);
+        b.insert("bg", jobs::bg);
         b.insert("cd", cd);
+        b.insert("command", command);
+        b.insert("echo", printf::echo);
+        b.insert_eval("eval", eval);
+        b.insert("exec", exec);
+        b.insert_eval("exit", exit);
         b.insert("export", export);
+        b.insert("fg", jobs::fg);
+        b.insert("getopts", getopts);
+        b.insert("hash", hash);
+        b.insert("jobs", jobs::jobs);
+        b.insert("kill", jobs::kill);
+        b.insert("printf", printf::printf);
+        b.insert("pwd", pwd);
+        b.insert("read", read);
         b.insert("readonly", readonly);
+        b.insert("set", set);
+        b.insert("test", test::test);
+        b.insert("times", resource::times);
+        b.insert("trap", trap::trap);
+        b.insert("type", type_);
+        b.insert("ulimit", resource::ulimit);
+        b.insert("umask", resource::umask);
+        b.insert("unalias", unalias);
         b.insert("unset", unset);
+        b.insert("wait", jobs::wait);
         b
     }
 
     fn insert(&mut self, key: &str, val: Command) {
-        self.commands.insert(key.to_string(), val);
+        self.commands.insert(key.to_string(), Builtin::Command(val));
     }
 
-    pub fn get(&self, key: &str) -> Option<&Command> {
+    fn insert_eval(&mut self, key: &str, val: EvalCommand) {
+        self.commands.insert(key.to_string(), Builtin::Eval(val));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Builtin> {
         self.commands.get(key)
     }
 }
 
-fn cd(args: &[String], context: &mut Context, stdio: StdIo) -> i32 {
-    if args.len() > 2 {
-        stdio.eprintln(format_args!("rash: too many arguments"));
-        return 1;
+/// The builtins POSIX calls special. Errors in them are more serious and variable
+/// assignments before them stay in effect after they run.
+const SPECIAL: &[&str] = &[
+    ".", ":", "break", "continue", "eval", "exec", "exit", "export", "readonly", "return", "set",
+    "shift", "times", "trap", "unset",
+];
+
+pub fn is_special(name: &str) -> bool {
+    SPECIAL.contains(&name)
+}
+
+/// Runs a file's commands in the current shell, searching PATH for it if its name
+/// has no slash. Arguments after the file replace the positional parameters while it runs.
+fn dot(args: &[String], eval: &mut Eval, stdio: &FdTable) -> i32 {
+    let Some(name) = args.get(1) else {
+        stdio.eprintln(format_args!("rash: .: usage: . file [argument...]"));
+        return 2;
+    };
+
+    let path = if name.contains('/') {
+        Some(PathBuf::from(name))
+    } else {
+        let path = eval.context.env.get("PATH").unwrap_or(exec::DEFAULT_PATH);
+        env::split_paths(path)
+            .map(|dir| dir.join(name))
+            .find(|file| file.is_file())
+    };
+    let input = match path.map(std::fs::read::<PathBuf>) {
+        Some(Ok(input)) => String::from_utf8_lossy(&input).into_owned(),
+        Some(Err(e)) => {
+            stdio.eprintln(format_args!(
+                "rash: .: {}: {}",
+                name,
+                Errno::from_raw(e.raw_os_error().unwrap_or(0)).desc()
+            ));
+            return 1;
+        }
+        None => {
+            stdio.eprintln(format_args!("rash: .: {}: not found", name));
+            return 1;
+        }
+    };
+
+    let positional = (args.len() > 2)
+        .then(|| std::mem::replace(&mut eval.context.positional, args[2..].to_vec()));
+    eval.context.last_return = 0;
+    let mut lines = input.split_terminator('\n');
+    eval.eval_lines(|_| lines.next().map(str::to_string));
+    if let Some(positional) = positional {
+        eval.context.positional = positional;
+    }
+    eval.context.last_return
+}
+
+/// Runs the arguments, joined with spaces, as shell commands.
+fn eval(args: &[String], eval: &mut Eval, _stdio: &FdTable) -> i32 {
+    eval.context.last_return = 0;
+    eval.eval_str(&args[1..].join(" "));
+    eval.context.last_return
+}
+
+/// Defines aliases given as name=value, or prints the named aliases or all of them.
+fn alias(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    if args.len() == 1 {
+        for (name, value) in &context.aliases {
+            stdio.println(format_args!("{}={}", name, quote(value)));
+        }
+        return 0;
     }
 
-    let dir = if args.len() == 1 {
-        context.env.get("HOME").unwrap_or("/")
-    } else if args[1].as_bytes() == [b'-'] {
-        if let Some(v) = context.env.get("OLDPWD") {
-            stdio.println(format_args!("{}", v));
-            v
+    let mut ret = 0;
+    for arg in &args[1..] {
+        if let Some((name, value)) = arg.split_once('=') {
+            if is_alias_name(name) {
+                context.aliases.insert(name.to_string(), value.to_string());
+            } else {
+                stdio.eprintln(format_args!("rash: alias: {}: invalid alias name", name));
+                ret = 1;
+            }
+        } else if let Some(value) = context.aliases.get(arg) {
+            stdio.println(format_args!("{}={}", arg, quote(value)));
         } else {
-            stdio.eprintln(format_args!("rash: cd: -: OLDPWD not set"));
-            return 1;
+            stdio.eprintln(format_args!("rash: alias: {}: not found", arg));
+            ret = 1;
         }
+    }
+    ret
+}
+
+/// Alias names are made of the portable filename characters and `!`, `%`, `,` and `@`.
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.!%,@".contains(c))
+}
+
+/// Removes the named aliases, or all of them with -a.
+fn unalias(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    match args.get(1).map(|a| a.as_str()) {
+        None => {
+            stdio.eprintln(format_args!("rash: unalias: usage: unalias [-a] name..."));
+            return 2;
+        }
+        Some("-a") => {
+            context.aliases.clear();
+            return 0;
+        }
+        _ => {}
+    }
+
+    let mut ret = 0;
+    for name in &args[1..] {
+        if context.aliases.remove(name).is_none() {
+            stdio.eprintln(format_args!("rash: unalias: {}: not found", name));
+            ret = 1;
+        }
+    }
+    ret
+}
+
+/// Finds the command that `command` should run when it isn't asked to describe one.
+/// Returns the index of the command in args and whether -p was given.
+/// The evaluator runs the command itself in place of the `command` builtin.
+pub fn command_operand(args: &[String]) -> Option<(usize, bool)> {
+    if args.first()? != "command" {
+        return None;
+    }
+    let mut default_path = false;
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        match arg.as_str() {
+            "--" => return (i + 1 < args.len()).then_some((i + 1, default_path)),
+            "-p" => default_path = true,
+            a if a.starts_with('-') && a.len() > 1 => return None,
+            _ => return Some((i, default_path)),
+        }
+    }
+    None
+}
+
+/// `command -v` or `command -V` describes how each name would be run. Running a
+/// command is handled by the evaluator, so without either option there is nothing to do.
+fn command(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut default_path = false;
+    let mut verbose = None;
+    let mut i = 1;
+    while let Some(arg) = args.get(i).filter(|a| a.starts_with('-') && a.len() > 1) {
+        i += 1;
+        if arg == "--" {
+            break;
+        }
+        for c in arg[1..].chars() {
+            match c {
+                'p' => default_path = true,
+                'v' => verbose = Some(false),
+                'V' => verbose = Some(true),
+                _ => {
+                    stdio.eprintln(format_args!("rash: command: -{}: invalid option", c));
+                    return 2;
+                }
+            }
+        }
+    }
+    let Some(verbose) = verbose else {
+        return 0;
+    };
+
+    let mut ret = 0;
+    for name in &args[i..] {
+        match describe(context, name, verbose, default_path) {
+            Some(description) => stdio.println(format_args!("{}", description)),
+            None => {
+                if verbose {
+                    stdio.eprintln(format_args!("rash: command: {}: not found", name));
+                }
+                ret = 1;
+            }
+        }
+    }
+    ret
+}
+
+/// Describes how a command name would be run, as `type` and `command -V` do when verbose
+/// is set. Otherwise returns just the name or the path of the file to run, as `command -v` does.
+fn describe(context: &Context, name: &str, verbose: bool, default_path: bool) -> Option<String> {
+    if let Some(value) = context.aliases.get(name) {
+        return Some(if verbose {
+            format!("{} is an alias for {}", name, value)
+        } else {
+            format!("alias {}={}", name, quote(value))
+        });
+    }
+
+    let kind = if lexer::RESERVED_WORDS.contains(&name) {
+        "a shell keyword"
+    } else if context.builtins.get(name).is_some() {
+        "a shell builtin"
     } else {
-        &args[1]
+        let path = if default_path {
+            exec::DEFAULT_PATH
+        } else {
+            context.env.get("PATH").unwrap_or(exec::DEFAULT_PATH)
+        };
+        let (file, hashed) = if name.contains('/') {
+            let runnable = Path::new(name).is_file() && access(name, AccessFlags::X_OK).is_ok();
+            (runnable.then(|| name.to_string())?, false)
+        } else if let Some(hashed) = context.hash.get(name, path) {
+            (hashed.to_string(), true)
+        } else {
+            (exec::search_path(name, path)?, false)
+        };
+        return Some(match (verbose, hashed) {
+            (false, _) => file,
+            (true, false) => format!("{} is {}", name, file),
+            (true, true) => format!("{} is hashed ({})", name, file),
+        });
     };
 
-    let path = Path::new(&dir);
-    let old = env::current_dir();
+    Some(if verbose {
+        format!("{} is {}", name, kind)
+    } else {
+        name.to_string()
+    })
+}
 
-    match env::set_current_dir(path) {
-        Ok(_) => {
-            if let Ok(oldpwd) = old {
-                context
-                    .env
-                    .set_var("OLDPWD", oldpwd.to_string_lossy().to_string(), None);
+/// Changes the working directory and sets PWD and OLDPWD.
+/// With `-L`, the default, `..` removes the last component of the logical path so
+/// that it leads back out of a symbolic link. With `-P`, symbolic links are
+/// resolved first and PWD is set to the physical path.
+fn cd(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    let mut physical = false;
+    while let Some(arg) = args.first() {
+        if arg == "--" {
+            args = &args[1..];
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            break;
+        }
+        for c in arg[1..].chars() {
+            match c {
+                'L' => physical = false,
+                'P' => physical = true,
+                c => {
+                    stdio.eprintln(format_args!("rash: cd: -{}: invalid option", c));
+                    return 2;
+                }
+            }
+        }
+        args = &args[1..];
+    }
+    if args.len() > 1 {
+        stdio.eprintln(format_args!("rash: too many arguments"));
+        return 1;
+    }
+
+    let dir = match args.first() {
+        None => context.env.get("HOME").unwrap_or("/"),
+        Some(dir) if dir == "-" => {
+            if let Some(v) = context.env.get("OLDPWD") {
+                v
+            } else {
+                stdio.eprintln(format_args!("rash: cd: -: OLDPWD not set"));
+                return 1;
             }
+        }
+        Some(dir) => dir,
+    }
+    .to_string();
+
+    let old = working_dir(context, false);
+    let path = if physical || dir.starts_with('/') {
+        normalize_path(&dir, physical)
+    } else {
+        match &old {
+            Some(old) => normalize_path(&format!("{}/{}", old, dir), false),
+            None => dir.clone(),
+        }
+    };
 
+    match env::set_current_dir(&path) {
+        Ok(_) => {
+            if let Some(oldpwd) = old {
+                context.env.set_var("OLDPWD", oldpwd, None);
+            }
+            let pwd = if physical {
+                working_dir(context, true).unwrap_or(path)
+            } else {
+                path
+            };
+            if args.first().map(|a| a.as_str()) == Some("-") {
+                stdio.println(format_args!("{}", pwd));
+            }
+            context.env.set_var("PWD", pwd, Some(true));
             0
         }
         Err(e) => {
             stdio.eprintln(format_args!(
                 "rash: cd: {}: {}",
-                path.display(),
+                dir,
                 Errno::from_raw(e.raw_os_error().unwrap()).desc()
             ));
             1
@@ -87,7 +419,147 @@ fn cd(args: &[String], context: &mut Context, stdio: StdIo) -> i32 {
     }
 }
 
-fn export(args: &[String], context: &mut Context, stdio: StdIo) -> i32 {
+/// Writes the working directory. With `-L`, the default, this is PWD when it names
+/// the working directory, and with `-P` it is the path without symbolic links.
+fn pwd(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut physical = false;
+    for arg in args[1..]
+        .iter()
+        .take_while(|a| a.starts_with('-') && a.len() > 1)
+    {
+        if arg == "--" {
+            break;
+        }
+        for c in arg[1..].chars() {
+            match c {
+                'L' => physical = false,
+                'P' => physical = true,
+                c => {
+                    stdio.eprintln(format_args!("rash: pwd: -{}: invalid option", c));
+                    return 2;
+                }
+            }
+        }
+    }
+
+    match working_dir(context, physical) {
+        Some(dir) => {
+            stdio.println(format_args!("{}", dir));
+            0
+        }
+        None => {
+            stdio.eprintln(format_args!("rash: pwd: {}", Errno::last().desc()));
+            1
+        }
+    }
+}
+
+/// Returns PWD if it is an absolute path to the working directory without `.` or `..`
+/// components, or otherwise the physical path of the working directory.
+pub fn working_dir(context: &Context, physical: bool) -> Option<String> {
+    let logical = context.env.get("PWD").filter(|pwd| {
+        pwd.starts_with('/')
+            && !pwd.split('/').any(|c| c == "." || c == "..")
+            && same_file(Path::new(pwd), Path::new("."))
+    });
+    match logical {
+        Some(pwd) if !physical => Some(pwd.to_string()),
+        _ => env::current_dir()
+            .ok()
+            .map(|dir| dir.to_string_lossy().into_owned()),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Removes `.` components and empty components from a path, and unless `physical`
+/// is set also removes each `..` with the component before it. A relative path keeps
+/// the `..` components which have nothing before them to remove.
+fn normalize_path(path: &str, physical: bool) -> String {
+    let mut components: Vec<&str> = vec![];
+    for c in path.split('/') {
+        match c {
+            "" | "." => {}
+            ".." if !physical && components.last().is_some_and(|&c| c != "..") => {
+                components.pop();
+            }
+            ".." if !physical && path.starts_with('/') => {}
+            c => components.push(c),
+        }
+    }
+    let joined = components.join("/");
+    if path.starts_with('/') {
+        format!("/{}", joined)
+    } else if joined.is_empty() {
+        ".".to_string()
+    } else {
+        joined
+    }
+}
+
+/// Replaces the shell with a command. With no command, the redirections
+/// are made permanent in the shell instead.
+fn exec(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    if args.first().map(|a| a.as_str()) == Some("--") {
+        args = &args[1..];
+    }
+
+    // the redirections last, so they can't be allowed to take the terminal away
+    context.jobs.relocate_terminal(stdio);
+    if let Err(e) = stdio.apply() {
+        stdio.eprintln(format_args!("rash: exec: {}", e.desc()));
+        return 1;
+    }
+
+    if args.is_empty() {
+        context
+            .exec_fds
+            .extend(stdio.descriptors().filter(|&fd| fd > 2));
+        for fd in stdio.closed() {
+            context.exec_fds.remove(&fd);
+        }
+        return 0;
+    }
+
+    // the command gets the signal dispositions of a child rather than the shell's.
+    // the EXIT trap doesn't run because the shell doesn't exit, it is replaced.
+    let saved = context.traps.clone();
+    context.traps.reset_for_subshell();
+    let Err(e) = exec::exec(context, &args[0], args, &[]);
+
+    let (message, status) = exec::exec_error(e);
+    stdio.eprintln(format_args!("rash: exec: {}: {}", args[0], message));
+    if !context.interactive {
+        std::process::exit(status);
+    }
+    context.traps.restore(saved);
+    status
+}
+
+/// Exits the shell with the given status or with the status of the last command.
+fn exit(args: &[String], eval: &mut Eval, stdio: &FdTable) -> i32 {
+    let status = match args.get(1) {
+        None => eval.context.last_return,
+        Some(n) => match n.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                stdio.eprintln(format_args!("rash: exit: {}: numeric argument required", n));
+                2
+            }
+        },
+    };
+    eval.exit(status);
+    // there were stopped jobs
+    1
+}
+
+fn export(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
     if args.len() == 1 || args[1] == "-p" {
         context
             .env
@@ -114,7 +586,264 @@ fn export(args: &[String], context: &mut Context, stdio: StdIo) -> i32 {
     0
 }
 
-fn readonly(args: &[String], context: &mut Context, stdio: StdIo) -> i32 {
+/// Reads a line from stdin and splits it into fields on IFS, assigning one field to
+/// each variable and the rest of the line to the last. Unless -r is given, a backslash
+/// quotes the next character and a backslash at the end of a line continues it.
+/// Returns 1 if the end of input was reached before a newline.
+fn read(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    let mut raw = false;
+    while let Some(arg) = args.first() {
+        match arg.as_str() {
+            "-r" => raw = true,
+            "--" => {
+                args = &args[1..];
+                break;
+            }
+            a if a.starts_with('-') && a.len() > 1 => {
+                stdio.eprintln(format_args!("rash: read: {}: invalid option", a));
+                return 2;
+            }
+            _ => break,
+        }
+        args = &args[1..];
+    }
+    if args.is_empty() {
+        stdio.eprintln(format_args!("rash: read: usage: read [-r] var..."));
+        return 2;
+    }
+
+    // characters of the line and whether each was quoted with a backslash
+    let mut chars: Vec<(char, bool)> = vec![];
+    let complete = loop {
+        let (bytes, complete) = match input::read_line(stdio.get(0).unwrap_or(-1)) {
+            Ok(line) => line,
+            Err(Errno::EINTR) if trap::pending_signal().is_some() => {
+                return 128 + trap::pending_signal().unwrap() as i32;
+            }
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                stdio.eprintln(format_args!("rash: read: {}", e.desc()));
+                return 2;
+            }
+        };
+
+        let line = String::from_utf8_lossy(&bytes);
+        let mut line_chars = line.chars();
+        let mut continued = false;
+        while let Some(c) = line_chars.next() {
+            if c == '\\' && !raw {
+                match line_chars.next() {
+                    Some(c) => chars.push((c, true)),
+                    None => continued = complete,
+                }
+            } else {
+                chars.push((c, false));
+            }
+        }
+        if !continued {
+            break complete;
+        }
+    };
+
+    let ifs = context.env.get("IFS").unwrap_or(" \t\n").to_string();
+    let fields = split_fields(&chars, &ifs, args.len());
+    for (name, value) in args.iter().zip(fields) {
+        context.set_var(name, value);
+    }
+
+    if complete {
+        0
+    } else {
+        1
+    }
+}
+
+/// Splits characters into at most count fields for read. Sequences of IFS whitespace
+/// delimit fields, as does another IFS character along with any whitespace around it.
+/// The last field holds the rest of the line without trailing IFS whitespace.
+fn split_fields(chars: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let is_ifs = |&(c, quoted): &(char, bool)| !quoted && ifs.contains(c);
+    let is_space = |ch: &(char, bool)| is_ifs(ch) && " \t\n".contains(ch.0);
+    let skip_space = |mut pos: usize| {
+        while pos < chars.len() && is_space(&chars[pos]) {
+            pos += 1;
+        }
+        pos
+    };
+    let text = |chars: &[(char, bool)]| chars.iter().map(|&(c, _)| c).collect::<String>();
+
+    let mut fields = vec![];
+    let mut pos = skip_space(0);
+    while fields.len() + 1 < count && pos < chars.len() {
+        let start = pos;
+        while pos < chars.len() && !is_ifs(&chars[pos]) {
+            pos += 1;
+        }
+        fields.push(text(&chars[start..pos]));
+
+        pos = skip_space(pos);
+        if pos < chars.len() && is_ifs(&chars[pos]) && !is_space(&chars[pos]) {
+            pos = skip_space(pos + 1);
+        }
+    }
+
+    let mut end = chars.len();
+    while end > pos && is_space(&chars[end - 1]) {
+        end -= 1;
+    }
+    fields.push(text(&chars[pos..end]));
+    fields.resize(count, String::new());
+    fields
+}
+
+/// Parses the next option from the positional parameters or the given arguments.
+/// The option is assigned to the named variable and its argument, if it takes one, to OPTARG.
+/// OPTIND holds the index of the next argument to parse. Returns 1 after the last option.
+fn getopts(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    if args.len() < 3 {
+        stdio.eprintln(format_args!(
+            "rash: getopts: usage: getopts optstring name [arg...]"
+        ));
+        return 2;
+    }
+    let (optstring, name) = (&args[1], &args[2]);
+    let operands = if args.len() > 3 {
+        args[3..].to_vec()
+    } else {
+        context.positional.clone()
+    };
+    // a leading colon means errors are left to the script to report
+    let (silent, optstring) = match optstring.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, optstring.as_str()),
+    };
+
+    let mut optind = context
+        .env
+        .get("OPTIND")
+        .and_then(|i| i.parse::<usize>().ok())
+        .filter(|&i| i > 0)
+        .unwrap_or(1);
+    let arg: Vec<char> = operands
+        .get(optind - 1)
+        .map_or(vec![], |a| a.chars().collect());
+    // continue within a group of flags unless OPTIND was assigned since the last call.
+    // the arguments may have changed too, so start over if the group is gone.
+    let mut offset = match context.env.getopts_offset() {
+        offset if offset > 0 && offset < arg.len() => offset,
+        _ => 1,
+    };
+    if offset == 1 && (arg.len() < 2 || arg[0] != '-' || arg == ['-', '-']) {
+        if arg == ['-', '-'] {
+            optind += 1;
+        }
+        context.set_var("OPTIND", optind.to_string());
+        context.set_var(name, "?".to_string());
+        context.env.unset("OPTARG");
+        return 1;
+    }
+
+    let c = arg[offset];
+    offset += 1;
+    let takes_argument = match optstring.find(c) {
+        Some(i) if c != ':' => optstring[i + c.len_utf8()..].starts_with(':'),
+        _ => {
+            if silent {
+                context.set_var("OPTARG", c.to_string());
+            } else {
+                stdio.eprintln(format_args!("rash: getopts: illegal option -- {}", c));
+                context.env.unset("OPTARG");
+            }
+            context.set_var(name, "?".to_string());
+            if offset >= arg.len() {
+                optind += 1;
+                offset = 0;
+            }
+            context.set_var("OPTIND", optind.to_string());
+            context.env.set_getopts_offset(offset);
+            return 0;
+        }
+    };
+
+    let mut result = c.to_string();
+    if takes_argument {
+        // the argument is the rest of this word or else the next one
+        let optarg = if offset < arg.len() {
+            Some(arg[offset..].iter().collect::<String>())
+        } else {
+            let next = operands.get(optind).cloned();
+            if next.is_some() {
+                optind += 1;
+            }
+            next
+        };
+        match optarg {
+            Some(optarg) => context.set_var("OPTARG", optarg),
+            None if silent => {
+                context.set_var("OPTARG", c.to_string());
+                result = ":".to_string();
+            }
+            None => {
+                stdio.eprintln(format_args!(
+                    "rash: getopts: option requires an argument -- {}",
+                    c
+                ));
+                context.env.unset("OPTARG");
+                result = "?".to_string();
+            }
+        }
+        offset = arg.len();
+    } else {
+        context.env.unset("OPTARG");
+    }
+
+    if offset >= arg.len() {
+        optind += 1;
+        offset = 0;
+    }
+    context.set_var(name, result);
+    context.set_var("OPTIND", optind.to_string());
+    context.env.set_getopts_offset(offset);
+    0
+}
+
+/// Lists the remembered command paths, forgets them all with -r,
+/// or looks up the named commands and remembers where they are.
+fn hash(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let path = context
+        .env
+        .get("PATH")
+        .unwrap_or(exec::DEFAULT_PATH)
+        .to_string();
+
+    match args.get(1).map(|a| a.as_str()) {
+        None => {
+            for (_, file) in context.hash.iter(&path) {
+                stdio.println(format_args!("{}", file));
+            }
+            return 0;
+        }
+        Some("-r") => {
+            context.hash.clear();
+            return 0;
+        }
+        _ => {}
+    }
+    let mut ret = 0;
+    for name in &args[1..] {
+        if name.contains('/') || context.builtins.get(name).is_some() {
+            continue;
+        }
+        if context.hash.find(name, &path).is_none() {
+            stdio.eprintln(format_args!("rash: hash: {}: not found", name));
+            ret = 1;
+        }
+    }
+    ret
+}
+
+fn readonly(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
     if args.len() == 1 || args[1] == "-p" {
         context
             .env
@@ -142,10 +871,394 @@ fn readonly(args: &[String], context: &mut Context, stdio: StdIo) -> i32 {
     0
 }
 
-fn unset(args: &[String], context: &mut Context, _stdio: StdIo) -> i32 {
+/// Describes how each name would be run.
+fn type_(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut ret = 0;
+    for name in &args[1..] {
+        match describe(context, name, true, false) {
+            Some(description) => stdio.println(format_args!("{}", description)),
+            None => {
+                stdio.eprintln(format_args!("rash: type: {}: not found", name));
+                ret = 1;
+            }
+        }
+    }
+    ret
+}
+
+fn unset(args: &[String], context: &mut Context, _stdio: &FdTable) -> i32 {
     for arg in &args[1..] {
         context.env.unset(arg)
     }
 
     0
 }
+
+fn set(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    if args.len() == 1 {
+        let mut vars: Vec<_> = context
+            .env
+            .iter()
+            .filter_map(|(k, v)| v.value.as_ref().map(|val| (k, val)))
+            .collect();
+        vars.sort();
+        for (k, val) in vars {
+            stdio.println(format_args!("{}={}", k, quote(val)));
+        }
+        return 0;
+    }
+
+    let mut i = 1;
+    let mut set_positional = false;
+    while i < args.len() {
+        let arg = &args[i];
+        let enable = match arg.as_bytes().first() {
+            Some(b'-') => true,
+            Some(b'+') => false,
+            _ => break,
+        };
+        if arg == "--" || arg == "-" {
+            i += 1;
+            set_positional = true;
+            break;
+        }
+        if arg == "-o" || arg == "+o" {
+            i += 1;
+            match args.get(i) {
+                None => list_options(context, enable, stdio),
+                Some(name) => match context::Options::flag_for_name(name) {
+                    Some(flag) => {
+                        context.options.set(flag, enable);
+                    }
+                    None => {
+                        stdio.eprintln(format_args!("rash: set: {}: invalid option name", name));
+                        return 2;
+                    }
+                },
+            }
+            i += 1;
+            continue;
+        }
+        for flag in arg.chars().skip(1) {
+            if !context.options.set(flag, enable) {
+                stdio.eprintln(format_args!(
+                    "rash: set: {}{}: invalid option",
+                    &arg[..1],
+                    flag
+                ));
+                return 2;
+            }
+        }
+        i += 1;
+    }
+
+    if set_positional || i < args.len() {
+        context.positional = args[i..].to_vec();
+    }
+
+    0
+}
+
+/// prints options for `set -o` in a human readable form or for `set +o` as commands
+fn list_options(context: &Context, human: bool, stdio: &FdTable) {
+    for (flag, name) in context::Options::ALL {
+        let on = context.options.get(*flag).unwrap_or(false);
+        if human {
+            stdio.println(format_args!(
+                "{:<16}{}",
+                name,
+                if on { "on" } else { "off" }
+            ));
+        } else {
+            stdio.println(format_args!("set {}o {}", if on { '-' } else { '+' }, name));
+        }
+    }
+}
+
+/// quotes a value so that it can be read back in as a single word
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment;
+
+    #[test]
+    fn describe_commands() {
+        let mut context = Context::new(false, environment::empty(), "rash".to_string());
+        context
+            .env
+            .set_var("PATH", "/nonexistent".to_string(), None);
+        context
+            .aliases
+            .insert("ll".to_string(), "ls -l".to_string());
+
+        let cases: &[(&str, bool, Option<&str>)] = &[
+            ("ll", false, Some("alias ll='ls -l'")),
+            ("ll", true, Some("ll is an alias for ls -l")),
+            ("if", false, Some("if")),
+            ("if", true, Some("if is a shell keyword")),
+            ("cd", true, Some("cd is a shell builtin")),
+            ("/bin/sh", true, Some("/bin/sh is /bin/sh")),
+            ("/nonexistent/sh", true, None),
+            // only PATH is searched
+            ("sh", true, None),
+        ];
+        for &(name, verbose, expected) in cases {
+            assert_eq!(
+                describe(&context, name, verbose, false).as_deref(),
+                expected,
+                "{}",
+                name
+            );
+        }
+
+        // with -p only the default path is searched
+        assert_eq!(
+            describe(&context, "sh", false, true).as_deref(),
+            Some("/bin/sh")
+        );
+
+        context.env.set_var("PATH", "/bin".to_string(), None);
+        context.hash.find("sh", "/bin");
+        assert_eq!(
+            describe(&context, "sh", true, false).as_deref(),
+            Some("sh is hashed (/bin/sh)")
+        );
+    }
+
+    #[test]
+    fn normalize_logical_paths() {
+        assert_eq!(normalize_path("/a/b/../c", false), "/a/c");
+        assert_eq!(normalize_path("/a/./b//c/", false), "/a/b/c");
+        assert_eq!(normalize_path("/a/b/../../..", false), "/");
+        assert_eq!(normalize_path("/..", false), "/");
+        assert_eq!(normalize_path("/", false), "/");
+        assert_eq!(normalize_path("//a", false), "/a");
+    }
+
+    #[test]
+    fn normalize_physical_paths() {
+        assert_eq!(normalize_path("/a/b/../c", true), "/a/b/../c");
+        assert_eq!(normalize_path("/a/./b//c/", true), "/a/b/c");
+        assert_eq!(normalize_path("/..", true), "/..");
+    }
+
+    #[test]
+    fn normalize_relative_paths() {
+        assert_eq!(normalize_path("a/b/../c", false), "a/c");
+        assert_eq!(normalize_path("./a/", false), "a");
+        assert_eq!(normalize_path("a/..", false), ".");
+        assert_eq!(normalize_path("", false), ".");
+        assert_eq!(normalize_path(".", true), ".");
+        // leading .. components have nothing to remove
+        assert_eq!(normalize_path("../a", false), "../a");
+        assert_eq!(normalize_path("a/../../b", false), "../b");
+        assert_eq!(normalize_path("../../a/..", false), "../..");
+        assert_eq!(normalize_path("a/../b", true), "a/../b");
+    }
+
+    #[test]
+    fn logical_working_dir() {
+        let cwd = env::current_dir().unwrap().to_string_lossy().into_owned();
+        let mut context = Context::new(false, environment::empty(), "rash".to_string());
+
+        context.env.set_var("PWD", cwd.clone(), None);
+        assert_eq!(working_dir(&context, false), Some(cwd.clone()));
+        // a PWD which doesn't name the working directory is ignored
+        for pwd in ["/nonexistent", "relative", &format!("{}/.", cwd)] {
+            context.env.set_var("PWD", pwd.to_string(), None);
+            assert_eq!(working_dir(&context, false), Some(cwd.clone()), "{}", pwd);
+        }
+    }
+
+    /// splits a line for read, treating a backslash as quoting the next character
+    fn split(line: &str, ifs: &str, count: usize) -> Vec<String> {
+        let mut chars = vec![];
+        let mut it = line.chars();
+        while let Some(c) = it.next() {
+            match c {
+                '\\' => chars.push((it.next().unwrap(), true)),
+                c => chars.push((c, false)),
+            }
+        }
+        split_fields(&chars, ifs, count)
+    }
+
+    #[test]
+    fn split_whitespace() {
+        let ifs = " \t\n";
+        assert_eq!(split("  a  b\tc  ", ifs, 3), vec!["a", "b", "c"]);
+        // the last variable gets the rest of the line without trailing whitespace
+        assert_eq!(split("  a  b\tc  ", ifs, 2), vec!["a", "b\tc"]);
+        assert_eq!(split("  a  b\tc  ", ifs, 1), vec!["a  b\tc"]);
+        // missing fields are empty
+        assert_eq!(split("a", ifs, 3), vec!["a", "", ""]);
+        assert_eq!(split("", ifs, 2), vec!["", ""]);
+    }
+
+    #[test]
+    fn split_other_characters() {
+        assert_eq!(split("a:b:c", ":", 3), vec!["a", "b", "c"]);
+        assert_eq!(split("a:b:c", ":", 2), vec!["a", "b:c"]);
+        // each non-whitespace delimiter ends a field, even an empty one
+        assert_eq!(split("a::b", ":", 3), vec!["a", "", "b"]);
+        assert_eq!(split(":a", ":", 2), vec!["", "a"]);
+        // whitespace around a non-whitespace delimiter is part of the delimiter
+        assert_eq!(split(" a : b ", " :", 2), vec!["a", "b"]);
+        assert_eq!(split("a  b", " :", 2), vec!["a", "b"]);
+        // whitespace which isn't in IFS is kept
+        assert_eq!(split(" a : b ", ":", 2), vec![" a ", " b "]);
+    }
+
+    #[test]
+    fn split_empty_ifs() {
+        assert_eq!(split("  a b  ", "", 2), vec!["  a b  ", ""]);
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(split("a\\ b c", " \t\n", 2), vec!["a b", "c"]);
+        assert_eq!(split("a\\:b:c", ":", 2), vec!["a:b", "c"]);
+        // quoted trailing whitespace is kept
+        assert_eq!(split("a b\\ ", " \t\n", 2), vec!["a", "b "]);
+    }
+
+    /// runs getopts once, returning its status, the option and OPTARG
+    fn next_option(context: &mut Context, optstring: &str, args: &[&str]) -> (i32, String, String) {
+        let mut argv = vec![
+            "getopts".to_string(),
+            optstring.to_string(),
+            "o".to_string(),
+        ];
+        argv.extend(args.iter().map(|a| a.to_string()));
+        let status = getopts(&argv, context, &FdTable::quiet());
+        let var = |name| context.env.get(name).unwrap_or("").to_string();
+        (status, var("o"), var("OPTARG"))
+    }
+
+    /// runs getopts until it fails, returning each option with OPTARG and the final OPTIND
+    fn all_options(optstring: &str, args: &[&str]) -> (Vec<String>, String) {
+        let mut context = Context::new(false, environment::empty(), "rash".to_string());
+        let mut options = vec![];
+        loop {
+            match next_option(&mut context, optstring, args) {
+                (0, o, optarg) if optarg.is_empty() => options.push(o),
+                (0, o, optarg) => options.push(format!("{}{}", o, optarg)),
+                _ => break,
+            }
+        }
+        (options, context.env.get("OPTIND").unwrap().to_string())
+    }
+
+    #[test]
+    fn getopts_grouped_flags() {
+        assert_eq!(
+            all_options("ab", &["-a", "-b", "x"]),
+            (vec!["a".into(), "b".into()], "3".into())
+        );
+        assert_eq!(
+            all_options("ab", &["-ab", "x"]),
+            (vec!["a".into(), "b".into()], "2".into())
+        );
+        assert_eq!(
+            all_options("ab", &["-ba", "-a"]),
+            (vec!["b".into(), "a".into(), "a".into()], "3".into())
+        );
+        // an option's argument is the rest of the word or the next one
+        assert_eq!(
+            all_options("af:", &["-afx", "y"]),
+            (vec!["a".into(), "fx".into()], "2".into())
+        );
+        assert_eq!(
+            all_options("af:", &["-af", "x", "y"]),
+            (vec!["a".into(), "fx".into()], "3".into())
+        );
+        // operands end the options
+        assert_eq!(all_options("ab", &["x", "-a"]), (vec![], "1".into()));
+        assert_eq!(all_options("ab", &["-", "-a"]), (vec![], "1".into()));
+        assert_eq!(all_options("ab", &[]), (vec![], "1".into()));
+    }
+
+    #[test]
+    fn getopts_double_dash() {
+        assert_eq!(
+            all_options("ab", &["-a", "--", "-b"]),
+            (vec!["a".into()], "3".into())
+        );
+        assert_eq!(all_options("ab", &["--"]), (vec![], "2".into()));
+        // `--` as an option's argument doesn't end the options
+        assert_eq!(
+            all_options("f:b", &["-f", "--", "-b"]),
+            (vec!["f--".into(), "b".into()], "4".into())
+        );
+    }
+
+    #[test]
+    fn getopts_errors() {
+        let mut context = Context::new(false, environment::empty(), "rash".to_string());
+        // without a leading colon, errors set the option to ? and unset OPTARG
+        assert_eq!(
+            next_option(&mut context, "a", &["-x"]),
+            (0, "?".into(), "".into())
+        );
+        context.set_var("OPTIND", "1".to_string());
+        assert_eq!(
+            next_option(&mut context, "f:", &["-f"]),
+            (0, "?".into(), "".into())
+        );
+        assert_eq!(context.env.get("OPTARG"), None);
+
+        // with a leading colon, OPTARG holds the option and a missing argument gives :
+        context.set_var("OPTIND", "1".to_string());
+        assert_eq!(
+            next_option(&mut context, ":a", &["-x"]),
+            (0, "?".into(), "x".into())
+        );
+        context.set_var("OPTIND", "1".to_string());
+        assert_eq!(
+            next_option(&mut context, ":f:", &["-f"]),
+            (0, ":".into(), "f".into())
+        );
+        // a colon is never an option
+        context.set_var("OPTIND", "1".to_string());
+        assert_eq!(
+            next_option(&mut context, ":f:", &["-:"]),
+            (0, "?".into(), ":".into())
+        );
+        assert_eq!(context.env.get("OPTIND"), Some("2"));
+
+        // parsing carries on after an unknown option in a group
+        assert_eq!(
+            all_options(":ab", &["-axb"]),
+            (vec!["a".into(), "?x".into(), "b".into()], "2".into())
+        );
+    }
+
+    #[test]
+    fn getopts_reset() {
+        let mut context = Context::new(false, environment::empty(), "rash".to_string());
+        assert_eq!(next_option(&mut context, "ab", &["-ab"]).1, "a");
+        // assigning OPTIND starts over, even in the middle of a group
+        context.set_var("OPTIND", "1".to_string());
+        assert_eq!(next_option(&mut context, "ab", &["-ab"]).1, "a");
+        assert_eq!(next_option(&mut context, "ab", &["-ab"]).1, "b");
+
+        // new arguments which are shorter than the group don't continue it
+        context.set_var("OPTIND", "1".to_string());
+        assert_eq!(next_option(&mut context, "ab", &["-ab"]).1, "a");
+        assert_eq!(
+            next_option(&mut context, "ab", &["-a"]),
+            (0, "a".into(), "".into())
+        );
+        assert_eq!(context.env.get("OPTIND"), Some("2"));
+
+        context.positional = vec!["-ab".to_string()];
+        context.env.unset("OPTIND");
+        assert_eq!(next_option(&mut context, "ab", &[]).1, "a");
+        context.positional = vec!["-b".to_string()];
+        assert_eq!(next_option(&mut context, "ab", &[]).1, "b");
+    }
+}
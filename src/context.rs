@@ -8,51 +8,473 @@
 //
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::fs::File;
 use std::io::Write;
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::os::unix::io::FromRawFd;
 use std::os::unix::io::IntoRawFd;
 use std::os::unix::io::RawFd;
 
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::unistd::{close, dup2, Pid};
+
 use crate::builtins;
 use crate::environment;
+use crate::exec;
+use crate::jobs;
+use crate::trap;
 
 /// An evaluation context defines evaluation settings
 /// and stores the current shell state.
 pub struct Context {
     pub interactive: bool,
+    /// the name of the shell or script, `$0`
+    pub arg0: String,
+    /// the positional parameters `$1` through `$n`
+    pub positional: Vec<String>,
     pub last_return: i32,
+    /// the process id of the most recent background command, `$!`
+    pub last_background: Option<Pid>,
+    pub options: Options,
     pub builtins: builtins::Builtins,
     pub env: environment::Environment,
+    pub jobs: jobs::Jobs,
+    pub traps: trap::Traps,
+    /// alias names and the text substituted for them
+    pub aliases: BTreeMap<String, String>,
+    /// where commands were found in PATH
+    pub hash: exec::HashTable,
+    /// descriptors above stderr which `exec` opened in the shell and which commands inherit
+    pub exec_fds: BTreeSet<RawFd>,
 }
 
-/// encapsulates stdio file descriptors
-#[derive(Debug, Copy, Clone)]
-pub struct StdIo {
-    pub stdin: RawFd,
-    pub stdout: RawFd,
-    pub stderr: RawFd,
+impl Context {
+    pub fn new(interactive: bool, env: environment::Environment, arg0: String) -> Context {
+        let mut context = Context {
+            interactive,
+            arg0,
+            positional: vec![],
+            last_return: 0,
+            last_background: None,
+            options: Default::default(),
+            builtins: builtins::Builtins::new(),
+            env,
+            jobs: jobs::Jobs::new(),
+            traps: trap::Traps::new(interactive),
+            aliases: BTreeMap::new(),
+            hash: Default::default(),
+            exec_fds: BTreeSet::new(),
+        };
+        context.set_var("OPTIND", "1".to_string());
+        // an inherited PWD is kept only if it still names the working directory
+        if let Some(pwd) = builtins::working_dir(&context, false) {
+            context.env.set_var("PWD", pwd, Some(true));
+        }
+        context
+    }
+
+    /// Returns the descriptors a command starts with before its own redirections.
+    pub fn fd_table(&self) -> FdTable {
+        let mut fds = FdTable::new();
+        for &fd in &self.exec_fds {
+            fds.set(fd, fd);
+        }
+        fds
+    }
+
+    /// Writes shell input to stderr as it is read when the verbose option is set.
+    /// Every reader of shell input should pass each line through here before parsing it.
+    pub fn echo_input(&self, input: &str) {
+        if self.options.verbose {
+            eprintln!("{}", input);
+        }
+    }
+
+    /// Returns true if pipelines should be run as jobs in their own process groups.
+    pub fn job_control(&self) -> bool {
+        self.options.monitor && self.jobs.has_terminal()
+    }
+
+    /// Returns the export setting for a variable assignment,
+    /// forcing it when the allexport option is set.
+    pub fn export_flag(&self) -> Option<bool> {
+        if self.options.allexport {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Assigns a shell variable, exporting it if the allexport option is set.
+    pub fn set_var(&mut self, key: &str, val: String) {
+        let export = self.export_flag();
+        self.env.set_var(key, val, export);
+    }
 }
 
-impl StdIo {
-    pub fn println(&self, fmt: fmt::Arguments) {
-        unsafe {
-            let mut stdout: File = File::from_raw_fd(self.stdout);
-            writeln!(stdout, "{}", fmt).unwrap_or_else(|e| {
-                std::process::exit(e.raw_os_error().unwrap_or(74 /* EX_IOERR */))
-            });
-            stdout.into_raw_fd();
+/// Shell options which can be changed with the `set` builtin
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Options {
+    /// -a: export every variable that is assigned
+    pub allexport: bool,
+    /// -b: report background jobs after the command during which they finish instead of
+    /// waiting for the next prompt
+    pub notify: bool,
+    /// -m: run pipelines as jobs in separate process groups
+    pub monitor: bool,
+    /// -C: don't overwrite existing files with `>`
+    pub noclobber: bool,
+    /// -v: write input to stderr as it is read
+    pub verbose: bool,
+}
+
+impl Options {
+    /// All options as (flag, name) pairs in the order they are listed by `set -o`
+    pub const ALL: &'static [(char, &'static str)] = &[
+        ('a', "allexport"),
+        ('m', "monitor"),
+        ('C', "noclobber"),
+        ('b', "notify"),
+        ('v', "verbose"),
+    ];
+
+    pub fn get(&self, flag: char) -> Option<bool> {
+        match flag {
+            'a' => Some(self.allexport),
+            'b' => Some(self.notify),
+            'm' => Some(self.monitor),
+            'C' => Some(self.noclobber),
+            'v' => Some(self.verbose),
+            _ => None,
+        }
+    }
+
+    /// Sets an option by its flag. Returns false if there is no such option.
+    pub fn set(&mut self, flag: char, value: bool) -> bool {
+        match flag {
+            'a' => self.allexport = value,
+            'b' => self.notify = value,
+            'm' => self.monitor = value,
+            'C' => self.noclobber = value,
+            'v' => self.verbose = value,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn flag_for_name(name: &str) -> Option<char> {
+        Options::ALL
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(f, _)| *f)
+    }
+}
+
+/// The file descriptors a command runs with. Each descriptor number the command sees
+/// is mapped to a descriptor open in the shell. Descriptors opened for the command,
+/// like pipes and redirected files, are owned by the table and closed when it is dropped.
+#[derive(Debug)]
+pub struct FdTable {
+    fds: BTreeMap<RawFd, RawFd>,
+    owned: Vec<OwnedFd>,
+    /// descriptors closed for the command with `n>&-`
+    closed: BTreeSet<RawFd>,
+    /// the first error writing to one of the descriptors
+    write_error: Cell<Option<Errno>>,
+}
+
+impl Default for FdTable {
+    /// the shell's own stdin, stdout and stderr
+    fn default() -> Self {
+        FdTable {
+            fds: (0..=2).map(|fd| (fd, fd)).collect(),
+            owned: vec![],
+            closed: BTreeSet::new(),
+            write_error: Cell::new(None),
+        }
+    }
+}
+
+impl FdTable {
+    pub fn new() -> FdTable {
+        Default::default()
+    }
+
+    /// Returns the shell descriptor which the command sees as fd.
+    pub fn get(&self, fd: RawFd) -> Option<RawFd> {
+        self.fds.get(&fd).copied()
+    }
+
+    /// Maps fd to a descriptor which is owned elsewhere.
+    pub fn set(&mut self, fd: RawFd, target: RawFd) {
+        self.closed.remove(&fd);
+        self.fds.insert(fd, target);
+    }
+
+    /// Closes fd for the command.
+    pub fn close(&mut self, fd: RawFd) {
+        self.fds.remove(&fd);
+        self.closed.insert(fd);
+    }
+
+    /// Returns true if fd was closed for the command.
+    pub fn is_closed(&self, fd: RawFd) -> bool {
+        self.closed.contains(&fd)
+    }
+
+    /// Returns the descriptor numbers closed for the command.
+    pub fn closed(&self) -> impl Iterator<Item = RawFd> + '_ {
+        self.closed.iter().copied()
+    }
+
+    /// Maps fd to a descriptor which is closed along with the table.
+    /// The descriptor is moved above the numbers commands normally use so that closing
+    /// it can't close a descriptor which `exec` installed under the same number.
+    pub fn set_owned(&mut self, fd: RawFd, target: OwnedFd) {
+        let target = match fcntl(target.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(10)) {
+            Ok(moved) => unsafe { OwnedFd::from_raw_fd(moved) },
+            Err(_) => target,
+        };
+        self.closed.remove(&fd);
+        self.fds.insert(fd, target.as_raw_fd());
+        self.owned.push(target);
+    }
+
+    /// Returns the descriptor numbers the command sees.
+    pub fn descriptors(&self) -> impl Iterator<Item = RawFd> + '_ {
+        self.fds.keys().copied()
+    }
+
+    /// Moves the mapped descriptors into place in the current process and closes the
+    /// ones closed for the command. Descriptors which aren't mapped are left open.
+    pub fn apply(&self) -> nix::Result<()> {
+        // copy the targets above every mapped number first so that installing one
+        // descriptor can't clobber the target of another
+        let base = self.fds.keys().max().map_or(0, |&fd| fd + 1).max(10);
+        let mut moved = vec![];
+        for (&fd, &target) in &self.fds {
+            match fcntl(target, FcntlArg::F_DUPFD_CLOEXEC(base)) {
+                Ok(copy) => moved.push((fd, copy)),
+                Err(e) => {
+                    for &(_, copy) in &moved {
+                        let _ = close(copy);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let mut result = Ok(());
+        for &(fd, copy) in &moved {
+            if let Err(e) = dup2(copy, fd) {
+                result = Err(e);
+            }
+            let _ = close(copy);
         }
+        for &fd in &self.closed {
+            let _ = close(fd);
+        }
+        result
+    }
+
+    /// Moves the mapped descriptors into place in a forked child and closes every
+    /// other descriptor so that the command inherits only what it was given.
+    pub fn install(&self) -> nix::Result<()> {
+        self.apply()?;
+        for fd in open_fds() {
+            if !self.fds.contains_key(&fd) {
+                let _ = close(fd);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes bytes to the command's stdout as they are.
+    pub fn write(&self, buf: &[u8]) {
+        self.write_fd(1, buf);
+    }
+
+    pub fn println(&self, fmt: fmt::Arguments) {
+        self.write_fd(1, format!("{}\n", fmt).as_bytes());
     }
 
     pub fn eprintln(&self, fmt: fmt::Arguments) {
-        unsafe {
-            let mut stderr: File = File::from_raw_fd(self.stderr);
-            writeln!(stderr, "{}", fmt).unwrap_or_else(|e| {
-                std::process::exit(e.raw_os_error().unwrap_or(74 /* EX_IOERR */))
-            });
-            stderr.into_raw_fd();
+        self.write_fd(2, format!("{}\n", fmt).as_bytes());
+    }
+
+    /// Returns the first error writing to the descriptors and forgets it.
+    pub fn take_write_error(&self) -> Option<Errno> {
+        self.write_error.take()
+    }
+
+    /// Writes to a descriptor, remembering the first error for the caller to report.
+    fn write_fd(&self, fd: RawFd, buf: &[u8]) {
+        let result = match self.get(fd) {
+            Some(fd) => unsafe {
+                let mut file: File = File::from_raw_fd(fd);
+                let result = file.write_all(buf);
+                let _ = file.into_raw_fd();
+                result.map_err(|e| Errno::from_raw(e.raw_os_error().unwrap_or(0)))
+            },
+            None => Err(Errno::EBADF),
+        };
+        if let Err(e) = result {
+            self.write_error.set(self.write_error.get().or(Some(e)));
+        }
+    }
+}
+
+#[cfg(test)]
+impl FdTable {
+    /// The shell's descriptors with stderr discarded, for testing commands which
+    /// report errors.
+    pub fn quiet() -> FdTable {
+        let mut stdio = FdTable::new();
+        stdio.set_owned(2, OwnedFd::from(File::create("/dev/null").unwrap()));
+        stdio
+    }
+}
+
+/// Lists the descriptors open in this process.
+fn open_fds() -> Vec<RawFd> {
+    // the directory listing has its own descriptor, so collect before closing anything
+    match std::fs::read_dir("/dev/fd") {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => (0..256).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::fd::BorrowedFd;
+
+    fn read_all(fd: OwnedFd) -> String {
+        let mut out = String::new();
+        File::from(fd).read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn fd_mapping() {
+        let mut stdio = FdTable::new();
+        for fd in 0..=2 {
+            assert_eq!(stdio.get(fd), Some(fd));
+        }
+        stdio.set(1, 2);
+        stdio.set(5, 0);
+        assert_eq!(stdio.get(1), Some(2));
+        assert_eq!(stdio.get(5), Some(0));
+        assert_eq!(stdio.get(6), None);
+        assert_ne!(FdTable::quiet().get(2), Some(2));
+    }
+
+    #[test]
+    fn owned_fds_close_with_table() {
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        let mut stdio = FdTable::new();
+        stdio.set_owned(1, write_end);
+        stdio.println(format_args!("out"));
+        drop(stdio);
+        // the pipe is at end of file once the table which owned its write end is gone
+        assert_eq!(read_all(read_end), "out\n");
+    }
+
+    /// installs the write end of a new pipe as fd, returning the read end
+    fn pipe_at(fd: RawFd) -> OwnedFd {
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        dup2(write_end.as_raw_fd(), fd).unwrap();
+        read_end
+    }
+
+    fn write_to(fd: RawFd, s: &str) {
+        nix::unistd::write(unsafe { BorrowedFd::borrow_raw(fd) }, s.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn apply_overlapping_targets() {
+        // 3>&1 1>&2 2>&3 with the descriptors numbered from 200
+        let one = pipe_at(201);
+        let two = pipe_at(202);
+        let mut stdio = FdTable::new();
+        stdio.set(203, 201);
+        stdio.set(201, 202);
+        stdio.set(202, stdio.get(203).unwrap());
+        stdio.apply().unwrap();
+
+        write_to(201, "a");
+        write_to(202, "b");
+        write_to(203, "c");
+        for fd in 201..=203 {
+            close(fd).unwrap();
+        }
+        assert_eq!(read_all(one), "bc");
+        assert_eq!(read_all(two), "a");
+    }
+
+    #[test]
+    fn owned_fds_are_moved_up() {
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        let original = write_end.as_raw_fd();
+        let mut stdio = FdTable::new();
+        stdio.set_owned(1, write_end);
+        let target = stdio.get(1).unwrap();
+        assert!(target >= 10 && target != original);
+        write_to(target, "moved");
+        drop(stdio);
+        assert_eq!(read_all(read_end), "moved");
+    }
+
+    #[test]
+    fn apply_closed_fds() {
+        let read_end = pipe_at(211);
+        let mut stdio = FdTable::new();
+        stdio.close(211);
+        assert!(stdio.is_closed(211));
+        assert_eq!(stdio.get(211), None);
+        stdio.apply().unwrap();
+        assert!(fcntl(211, FcntlArg::F_GETFD).is_err());
+        assert_eq!(read_all(read_end), "");
+
+        // mapping a closed descriptor again opens it
+        stdio.set(211, 1);
+        assert!(!stdio.is_closed(211));
+        assert_eq!(stdio.closed().count(), 0);
+    }
+
+    #[test]
+    fn write_errors_are_kept() {
+        let mut stdio = FdTable::new();
+        stdio.set_owned(1, OwnedFd::from(File::create("/dev/full").unwrap()));
+        stdio.close(2);
+        stdio.println(format_args!("full"));
+        stdio.eprintln(format_args!("closed"));
+        // only the first error is kept
+        assert_eq!(stdio.take_write_error(), Some(Errno::ENOSPC));
+        assert_eq!(stdio.take_write_error(), None);
+    }
+
+    #[test]
+    fn option_names() {
+        // every option flag has a name for set -o
+        for flag in ['a', 'b', 'm', 'C', 'v'] {
+            let named = Options::ALL.iter().any(|(f, _)| *f == flag);
+            assert!(named, "-{} has no name", flag);
+        }
+        assert_eq!(Options::flag_for_name("notify"), Some('b'));
+        assert_eq!(Options::flag_for_name("monitor"), Some('m'));
+        assert_eq!(Options::flag_for_name("nosuch"), None);
+
+        let mut options = Options::default();
+        for (flag, _) in Options::ALL {
+            assert!(options.set(*flag, true));
+            assert_eq!(options.get(*flag), Some(true));
         }
     }
 }
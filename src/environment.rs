@@ -22,10 +22,16 @@ pub struct Val {
 #[derive(Debug, Clone)]
 pub struct Environment {
     vars: HashMap<String, Val>,
+    /// variables assigned for the duration of a command, which it sees as exported
+    temporary: Vec<String>,
+    /// how far getopts got into the group of flags in the argument OPTIND names.
+    /// Any assignment to OPTIND starts the next call at the beginning of the argument.
+    getopts_offset: usize,
 }
 
 impl Environment {
     pub fn set_var(&mut self, key: &str, val: String, export: Option<bool>) {
+        self.assigned(key);
         match self.vars.entry(key.into()) {
             Entry::Occupied(mut o) => {
                 let v = o.get_mut();
@@ -46,13 +52,14 @@ impl Environment {
     }
 
     /// sets a variable of the form "KEY=VALUE"
-    pub fn set_vareq(&mut self, var_eq: &str) {
+    pub fn set_vareq(&mut self, var_eq: &str, export: Option<bool>) {
         if let Some((key, value)) = self.parse(var_eq) {
-            self.set_var(key, value.into(), None)
+            self.set_var(key, value.into(), export)
         }
     }
 
     pub fn unset(&mut self, key: &str) {
+        self.assigned(key);
         if let Entry::Occupied(o) = self.vars.entry(key.to_string()) {
             // TODO: return an error if readonly
             if !o.get().readonly {
@@ -61,6 +68,78 @@ impl Environment {
         }
     }
 
+    /// Assigns variables of the form "KEY=VALUE" for the duration of a command, which
+    /// sees them as exported. Returns their previous values to be put back with `restore`
+    /// or forgotten with `keep`.
+    pub fn assign_temporarily(&mut self, assignments: &[&str]) -> Vec<(String, Option<Val>)> {
+        let mut saved = vec![];
+        for var_eq in assignments {
+            if let Some((key, value)) = self.parse(var_eq) {
+                saved.push((key.to_string(), self.vars.get(key).cloned()));
+                self.assigned(key);
+                let v = self.vars.entry(key.to_string()).or_default();
+                if !v.readonly {
+                    v.value = Some(value.to_string());
+                }
+                self.temporary.push(key.to_string());
+            }
+        }
+        saved
+    }
+
+    /// Puts back the values saved by `assign_temporarily`. Attributes the command gave
+    /// the variables, like readonly, are kept.
+    pub fn restore(&mut self, saved: Vec<(String, Option<Val>)>) {
+        for (key, val) in saved.into_iter().rev() {
+            self.temporary.pop();
+            self.assigned(&key);
+            let value = val.and_then(|v| v.value);
+            match self.vars.entry(key) {
+                Entry::Occupied(mut o) => {
+                    let v = o.get_mut();
+                    if value.is_none() && !v.export && !v.readonly {
+                        o.remove();
+                    } else {
+                        v.value = value;
+                    }
+                }
+                Entry::Vacant(o) => {
+                    if value.is_some() {
+                        o.insert(Val {
+                            value,
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Makes the assignments of `assign_temporarily` last after the command, as they do
+    /// for special builtins. They stay exported only if `export` is set.
+    pub fn keep(&mut self, saved: Vec<(String, Option<Val>)>, export: Option<bool>) {
+        for (key, _) in saved.into_iter().rev() {
+            self.temporary.pop();
+            if export == Some(true) {
+                self.export(&key);
+            }
+        }
+    }
+
+    pub fn getopts_offset(&self) -> usize {
+        self.getopts_offset
+    }
+
+    pub fn set_getopts_offset(&mut self, offset: usize) {
+        self.getopts_offset = offset;
+    }
+
+    fn assigned(&mut self, key: &str) {
+        if key == "OPTIND" {
+            self.getopts_offset = 0;
+        }
+    }
+
     pub fn export(&mut self, key: &str) {
         self.vars
             .entry(key.to_string())
@@ -87,7 +166,7 @@ impl Environment {
     pub fn exports(&self) -> Vec<String> {
         self.vars
             .iter()
-            .filter(|(_, v)| v.export && v.value.is_some())
+            .filter(|(k, v)| (v.export || self.temporary.contains(k)) && v.value.is_some())
             .map(|(k, v)| format!("{}={}", k, v.value.as_ref().unwrap()))
             .collect()
     }
@@ -115,6 +194,8 @@ impl Environment {
 pub fn empty() -> Environment {
     Environment {
         vars: HashMap::new(),
+        temporary: vec![],
+        getopts_offset: 0,
     }
 }
 
@@ -125,3 +206,59 @@ pub fn from_system() -> Environment {
     });
     e
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_assignments() {
+        let mut env = empty();
+        env.set_var("IFS", " ".to_string(), None);
+        env.set_var("RO", "1".to_string(), None);
+        env.readonly("RO");
+
+        let saved = env.assign_temporarily(&["IFS=:", "NEW=x", "RO=2"]);
+        assert_eq!(env.get("IFS"), Some(":"));
+        assert_eq!(env.get("NEW"), Some("x"));
+        assert_eq!(env.get("RO"), Some("1"));
+        assert!(env.exports().contains(&"NEW=x".to_string()));
+
+        env.restore(saved);
+        assert_eq!(env.get("IFS"), Some(" "));
+        assert_eq!(env.get("NEW"), None);
+        assert_eq!(env.get("RO"), Some("1"));
+        assert!(env.exports().is_empty());
+    }
+
+    #[test]
+    fn restore_keeps_attributes() {
+        let mut env = empty();
+        env.set_var("OLD", "1".to_string(), None);
+
+        let saved = env.assign_temporarily(&["OLD=2", "NEW=x"]);
+        env.readonly("OLD");
+        env.export("NEW");
+        env.restore(saved);
+        assert_eq!(env.get("OLD"), Some("1"));
+        assert!(env.iter().any(|(k, v)| k == "OLD" && v.readonly));
+        // the variable stays exported, but without the value it had for the command
+        assert_eq!(env.get("NEW"), None);
+        assert!(env.iter().any(|(k, v)| k == "NEW" && v.export));
+    }
+
+    #[test]
+    fn kept_assignments() {
+        let mut env = empty();
+        let saved = env.assign_temporarily(&["X=5", "Y=1"]);
+        env.export("X");
+        env.keep(saved, None);
+        assert_eq!(env.get("X"), Some("5"));
+        assert_eq!(env.get("Y"), Some("1"));
+        assert_eq!(env.exports(), vec!["X=5".to_string()]);
+
+        let saved = env.assign_temporarily(&["Z=2"]);
+        env.keep(saved, Some(true));
+        assert!(env.exports().contains(&"Z=2".to_string()));
+    }
+}
@@ -9,24 +9,38 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 
 use std::iter::Peekable;
-use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::fd::{FromRawFd, OwnedFd};
 use std::os::unix::io::RawFd;
 use std::str::Chars;
 
-use nix::sys::wait;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::sys::stat::{stat, Mode, SFlag};
 use nix::unistd::*;
 
 use crate::ast;
+use crate::builtins;
 use crate::context;
 use crate::exec;
+use crate::grammar;
+use crate::jobs;
+use crate::lexer;
+use crate::trap;
 
 pub struct Eval {
     pub context: context::Context,
+    in_trap: bool,
+    /// set once the user has been warned that exiting would leave stopped jobs behind
+    warned_stopped: bool,
 }
 
 impl Eval {
     pub fn new(context: context::Context) -> Eval {
-        Eval { context }
+        Eval {
+            context,
+            in_trap: false,
+            warned_stopped: false,
+        }
     }
 
     pub fn eval(&mut self, program: &ast::Program) {
@@ -35,15 +49,133 @@ impl Eval {
         }
     }
 
+    /// Adds a line, which is echoed under `set -v`, to the input read so far and runs
+    /// the input once it forms complete commands. Returns false if more lines are needed.
+    /// Syntax errors are reported and set the return value to 2. The caller clears the
+    /// input after a true return.
+    pub fn eval_line(&mut self, input: &mut String, line: &str) -> bool {
+        self.context.echo_input(line);
+        input.push_str(line);
+        input.push('\n');
+
+        let parser = grammar::programParser::new();
+        // the aliases are copied because the parsed program may refer to their text
+        let aliases = self.context.aliases.clone();
+        match parser.parse(input, lexer::Lexer::with_aliases(input, &aliases)) {
+            Ok(program) => {
+                trace!("{:?}", program);
+                self.eval(&program);
+                true
+            }
+            Err(
+                lalrpop_util::ParseError::UnrecognizedEof { .. }
+                | lalrpop_util::ParseError::User {
+                    error: lexer::LexError::UnexpectedEOF(_),
+                },
+            ) => false,
+            Err(e) => {
+                eprintln!("rash: {}", e);
+                self.context.last_return = 2;
+                true
+            }
+        }
+    }
+
+    /// Evaluates the lines returned by `next_line` one complete command at a time, so that
+    /// the commands before a syntax error run and aliases apply to the lines after their
+    /// definition. Traps run between commands. A command left incomplete when the lines
+    /// run out is a syntax error.
+    pub fn eval_lines(&mut self, mut next_line: impl FnMut(&mut Eval) -> Option<String>) {
+        let mut input = String::new();
+        loop {
+            if input.is_empty() {
+                self.run_traps();
+            }
+            let Some(line) = next_line(self) else {
+                break;
+            };
+            if self.eval_line(&mut input, &line) {
+                input.clear();
+            }
+        }
+        if !input.is_empty() {
+            // parsing again reports the error
+            self.eval_str(&input);
+        }
+    }
+
+    /// Parses and evaluates a string of commands.
+    /// Syntax errors are reported and set the return value to 2.
+    pub fn eval_str(&mut self, input: &str) {
+        let parser = grammar::programParser::new();
+        let aliases = self.context.aliases.clone();
+        match parser.parse(input, lexer::Lexer::with_aliases(input, &aliases)) {
+            Ok(program) => self.eval(&program),
+            Err(e) => {
+                eprintln!("rash: {}", e);
+                self.context.last_return = 2;
+            }
+        }
+    }
+
+    /// Runs the trap actions of signals which arrived since the last check.
+    /// The return value of the interrupted command is preserved.
+    pub fn run_traps(&mut self) {
+        if self.in_trap {
+            return;
+        }
+        for signo in trap::take_pending() {
+            if let Some(trap::Action::Command(cmd)) = self.context.traps.get(signo) {
+                let cmd = cmd.clone();
+                let last_return = self.context.last_return;
+                self.in_trap = true;
+                self.eval_str(&cmd);
+                self.in_trap = false;
+                self.context.last_return = last_return;
+            }
+        }
+    }
+
+    /// Runs the EXIT trap action if one is set. It runs at most once.
+    pub fn run_exit_trap(&mut self) {
+        if let Some(cmd) = self.context.traps.take_exit() {
+            let last_return = self.context.last_return;
+            self.eval_str(&cmd);
+            self.context.last_return = last_return;
+        }
+    }
+
+    /// Runs the EXIT trap and exits with a status. With job control, if there are stopped
+    /// jobs, the first attempt warns about them and returns instead of exiting.
+    pub fn exit(&mut self, status: i32) {
+        if self.context.job_control()
+            && !self.warned_stopped
+            && self.context.jobs.iter().any(|job| job.is_stopped())
+        {
+            eprintln!("rash: there are stopped jobs");
+            self.warned_stopped = true;
+            return;
+        }
+        self.context.last_return = status;
+        self.run_exit_trap();
+        std::process::exit(status)
+    }
+
     fn complete_command(&mut self, cc: &ast::CompleteCommand) {
         for (op, list) in &cc.and_ors {
             self.andor_list(*op == ast::TermOp::Amp, list);
+            self.run_traps();
+            // with set -b, report background jobs as soon as control returns to the shell
+            if self.context.options.notify {
+                jobs::notify(&mut self.context);
+            }
         }
     }
 
     fn andor_list(&mut self, exec_async: bool, list: &ast::AndOr) {
         for (op, pipeline) in &list.pipelines {
             self.exec_pipeline(exec_async, pipeline);
+            self.run_traps();
             match op {
                 ast::AndOrOp::And => {
                     if self.context.last_return != 0 {
@@ -61,52 +193,56 @@ impl Eval {
 
     fn exec_pipeline(&mut self, exec_async: bool, pipeline: &ast::Pipeline) {
         let mut child_list: Vec<Pid> = vec![];
-        let mut owned_pipe_fds: Vec<OwnedFd> = vec![];
-        let mut next_stdin: RawFd = 0;
-        let mut cur_stdout: RawFd = 0;
+        let mut next_stdin: Option<OwnedFd> = None;
 
         let mut final_return: Option<i32> = None;
 
+        let job_control = self.context.job_control();
+        let mut pgid: Option<Pid> = None;
+
         for i in 0..pipeline.commands.len() {
-            // Create pipes between pipeline elements,
-            // set up stdin on the first element and stdout on the last element
-            let cur_stdin: RawFd;
-            if i == 0 {
-                cur_stdin = 0;
-                next_stdin = 0;
-                if pipeline.commands.len() == 1 {
-                    cur_stdout = 1;
-                } else if let Ok((r, w)) = pipe() {
-                    cur_stdout = w.as_raw_fd();
-                    next_stdin = r.as_raw_fd();
-                    owned_pipe_fds.push(r);
-                    owned_pipe_fds.push(w);
-                }
-            } else if i == pipeline.commands.len() - 1 {
-                cur_stdin = next_stdin;
-                cur_stdout = 1;
-            } else {
-                cur_stdin = next_stdin;
-                if let Ok((r, w)) = pipe() {
-                    cur_stdout = w.as_raw_fd();
-                    next_stdin = r.as_raw_fd();
-                    owned_pipe_fds.push(r);
-                    owned_pipe_fds.push(w);
+            // connect each element's stdout to the next element's stdin.
+            // the table closes the shell's copies of the pipe once the element is started.
+            let mut fds = self.context.fd_table();
+            if let Some(r) = next_stdin.take() {
+                fds.set_owned(0, r);
+            }
+            if i < pipeline.commands.len() - 1 {
+                match pipe2(OFlag::O_CLOEXEC) {
+                    Ok((r, w)) => {
+                        fds.set_owned(1, w);
+                        next_stdin = Some(r);
+                    }
+                    Err(e) => eprintln!("rash: pipe: {}", e.desc()),
                 }
-            };
+            }
 
             let command = &pipeline.commands[i];
             debug!("{:?}", command);
             match command {
-                ast::Command::Simple(ast::SimpleCommand { assign, cmd, args }) => {
+                ast::Command::Simple(ast::SimpleCommand {
+                    assign,
+                    cmd,
+                    args,
+                    redirect,
+                }) => {
                     let parsed_cmd = match cmd {
                         ast::Arg::Arg(s) => self.expand_arg(s),
                     };
 
+                    if let Err(e) = self.redirect(redirect, &mut fds) {
+                        eprintln!("rash: {}", e);
+                        if i == pipeline.commands.len() - 1 {
+                            final_return = Some(1);
+                        }
+                        continue;
+                    }
+
                     // assignments with no command change the current environment
                     if parsed_cmd.is_empty() {
+                        let export = self.context.export_flag();
                         for vareq in assign {
-                            self.context.env.set_vareq(vareq);
+                            self.context.env.set_vareq(vareq, export);
                         }
                         return;
                     }
@@ -116,17 +252,49 @@ impl Eval {
                         ast::Arg::Arg(s) => self.expand_arg(s),
                     }));
 
+                    // `command name args` runs name as if `command` weren't there.
+                    // with -p, name is searched for in the default PATH.
+                    let mut parsed_cmd = parsed_cmd;
+                    let mut not_found = false;
+                    while let Some((i, default_path)) = builtins::command_operand(&parsed_args) {
+                        parsed_args.drain(..i);
+                        parsed_cmd = parsed_args[0].clone();
+                        if default_path
+                            && !parsed_cmd.contains('/')
+                            && self.context.builtins.get(&parsed_cmd).is_none()
+                        {
+                            match exec::search_path(&parsed_cmd, exec::DEFAULT_PATH) {
+                                Some(path) => parsed_cmd = path,
+                                None => not_found = true,
+                            }
+                        }
+                    }
+                    if not_found {
+                        eprintln!("rash: {}: not found", parsed_cmd);
+                        if i == pipeline.commands.len() - 1 {
+                            final_return = Some(127);
+                        }
+                        continue;
+                    }
+
+                    // the warning about stopped jobs only holds off an exit right after it
+                    if parsed_cmd != "exit" {
+                        self.warned_stopped = false;
+                    }
+
                     if let Some(pid) = exec::run_command(
-                        &mut self.context,
+                        self,
                         &parsed_cmd,
                         &parsed_args,
                         assign,
-                        context::StdIo {
-                            stdin: cur_stdin,
-                            stdout: cur_stdout,
-                            stderr: 2,
-                        },
+                        &fds,
+                        job_control.then_some(jobs::ProcessGroup {
+                            pgid,
+                            foreground: !exec_async,
+                        }),
+                        pipeline.commands.len() > 1,
                     ) {
+                        pgid.get_or_insert(pid);
                         child_list.push(pid);
                     } else {
                         // if the last element in a pipeline is a built-in, record the return value
@@ -140,30 +308,96 @@ impl Eval {
         }
 
         if exec_async {
+            if let Some(&last) = child_list.last() {
+                let job = jobs::Job::new(&child_list, pipeline.to_string(), job_control);
+                let id = self.context.jobs.add(job);
+                self.context.last_background = Some(last);
+                if self.context.interactive {
+                    eprintln!("[{}] {}", id, last);
+                }
+            }
             // async commands always return 0
             self.context.last_return = 0;
             return;
         }
 
-        // Check children starting with the last one.
-        // Save the return code if it wasn't saved already.
-        for &child in child_list.iter().rev() {
-            let result = wait::waitpid(Some(child), None);
-            match result {
-                Ok(wait_status) => {
-                    if let wait::WaitStatus::Exited(_pid, r) = wait_status {
-                        final_return.get_or_insert(r);
-                    };
-                }
-                Err(e) => {
-                    eprintln!("rash: wait failed: {}", e);
+        if !child_list.is_empty() {
+            let mut job = jobs::Job::new(&child_list, pipeline.to_string(), job_control);
+            self.context.jobs.foreground(&mut job, false);
+
+            if let Some(r) = job.status() {
+                final_return.get_or_insert(r);
+            }
+            if self.context.interactive {
+                if let Some(message) = job.termination_message() {
+                    eprintln!("{}", message);
                 }
             }
+            if job.is_stopped() {
+                let id = self.context.jobs.add(job);
+                let job = self.context.jobs.get(id).unwrap();
+                eprintln!("\n{}", jobs::format_job(&self.context.jobs, job, false));
+            }
         }
 
         if let Some(i) = final_return {
             self.context.last_return = i;
         }
+        if pipeline.negated {
+            self.context.last_return = (self.context.last_return == 0) as i32;
+        }
+    }
+
+    /// Opens the files named by redirections and maps them into the command's descriptors.
+    fn redirect(
+        &self,
+        redirects: &[ast::Redirect],
+        fds: &mut context::FdTable,
+    ) -> Result<(), String> {
+        for r in redirects {
+            let target = match r.target {
+                ast::Arg::Arg(s) => self.expand_arg(s),
+            };
+
+            let opened = match r.op {
+                ast::RedirectOp::From => open_file(&target, OFlag::O_RDONLY),
+                ast::RedirectOp::To if self.context.options.noclobber => noclobber_open(&target),
+                ast::RedirectOp::To | ast::RedirectOp::Clobber => {
+                    open_file(&target, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC)
+                }
+                ast::RedirectOp::Append => {
+                    open_file(&target, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND)
+                }
+                ast::RedirectOp::FromTo => open_file(&target, OFlag::O_RDWR | OFlag::O_CREAT),
+                ast::RedirectOp::ToFd | ast::RedirectOp::FromFd if target == "-" => {
+                    fds.close(r.fd);
+                    continue;
+                }
+                ast::RedirectOp::ToFd | ast::RedirectOp::FromFd => {
+                    // duplicate whatever the command already has as that descriptor,
+                    // falling back to one the shell itself inherited
+                    let dup_fd = match target.parse::<RawFd>() {
+                        Ok(n) if fds.is_closed(n) => {
+                            return Err(format!("{}: {}", target, Errno::EBADF.desc()))
+                        }
+                        Ok(n) => fds.get(n).unwrap_or(n),
+                        Err(_) => return Err(format!("{}: bad file descriptor number", target)),
+                    };
+                    if let Err(e) = fcntl(dup_fd, FcntlArg::F_GETFD) {
+                        return Err(format!("{}: {}", target, e.desc()));
+                    }
+                    fds.set(r.fd, dup_fd);
+                    continue;
+                }
+            };
+
+            match opened {
+                Ok(fd) => fds.set_owned(r.fd, fd),
+                Err(e) => return Err(format!("{}: {}", target, e)),
+            }
+        }
+
+        Ok(())
     }
 
     fn expand_arg(&self, arg: &str) -> String {
@@ -240,22 +474,13 @@ impl Eval {
             false
         };
 
-        if chars.peek() == Some(&'?') {
-            chars.next();
-            let return_val = format!("{}", self.context.last_return);
-            expanded.push_str(&return_val);
-            return;
-        }
-
-        while chars.peek().is_some() {
-            let c = chars.next().unwrap();
-            if delimited {
+        if delimited {
+            while let Some(c) = chars.next() {
                 if c == '}' {
                     break;
                 }
-                if c == '-' {
-                    while chars.peek().is_some() {
-                        let c = chars.next().unwrap();
+                if c == '-' && !param.is_empty() {
+                    for c in chars.by_ref() {
                         if c == '}' {
                             break;
                         }
@@ -264,12 +489,203 @@ impl Eval {
                     break;
                 }
                 param.push(c);
-            } else if c.is_alphanumeric() || c == '_' {
-                param.push(c);
-            } else {
-                break;
             }
+        } else {
+            match chars.peek() {
+                // special parameters and $1 through $9 are a single character
+                Some(&c) if c.is_ascii_digit() || "?!#@*$".contains(c) => {
+                    param.push(c);
+                    chars.next();
+                }
+                _ => {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_alphanumeric() || c == '_' {
+                            param.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+            }
+            // a $ that doesn't start a parameter is literal
+            if param.is_empty() {
+                expanded.push('$');
+                return;
+            }
+        }
+
+        expanded.push_str(&self.param_value(&param).unwrap_or(default_val));
+    }
+
+    /// Returns the value of a special parameter, positional parameter, or variable.
+    fn param_value(&self, param: &str) -> Option<String> {
+        let context = &self.context;
+        match param {
+            "?" => Some(context.last_return.to_string()),
+            "!" => context.last_background.map(|pid| pid.to_string()),
+            "#" => Some(context.positional.len().to_string()),
+            "@" | "*" => Some(context.positional.join(" ")),
+            "$" => Some(getpid().to_string()),
+            "0" => Some(context.arg0.clone()),
+            _ => match param.parse::<usize>() {
+                Ok(0) => Some(context.arg0.clone()),
+                Ok(n) => context.positional.get(n - 1).cloned(),
+                Err(_) => context.env.get(param).map(|v| v.to_string()),
+            },
+        }
+    }
+}
+
+fn open_file(path: &str, flags: OFlag) -> Result<OwnedFd, String> {
+    // close on exec so that only the command the file is mapped into inherits it
+    open(
+        path,
+        flags | OFlag::O_CLOEXEC,
+        Mode::from_bits_truncate(0o666),
+    )
+    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+    .map_err(|e| e.desc().to_string())
+}
+
+/// Opens a file for writing without overwriting an existing regular file.
+/// Other existing files, like /dev/null, may still be written to.
+fn noclobber_open(path: &str) -> Result<OwnedFd, String> {
+    match stat(path) {
+        Ok(st)
+            if SFlag::from_bits_truncate(st.st_mode & SFlag::S_IFMT.bits()) != SFlag::S_IFREG =>
+        {
+            open_file(path, OFlag::O_WRONLY)
         }
-        expanded.extend(self.context.env.get(&param).or(Some(&default_val)));
+        Ok(_) => Err("cannot overwrite existing file".to_string()),
+        Err(_) => open_file(path, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn negated_pipeline() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+
+        eval.eval_str("test a = b");
+        assert_eq!(eval.context.last_return, 1);
+        eval.eval_str("! test a = b");
+        assert_eq!(eval.context.last_return, 0);
+        eval.eval_str("! test a = a");
+        assert_eq!(eval.context.last_return, 1);
+    }
+
+    #[test]
+    fn lines_are_run_one_command_at_a_time() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+
+        // an alias applies to the lines after its definition
+        let mut lines = ["alias no=false", "no"].into_iter();
+        eval.eval_lines(|_| lines.next().map(str::to_string));
+        assert_eq!(eval.context.last_return, 1);
+
+        // the commands before a syntax error run
+        let mut lines = ["x=1", "x=2 &&"].into_iter();
+        eval.eval_lines(|_| lines.next().map(str::to_string));
+        assert_eq!(eval.context.env.get("x"), Some("1"));
+        assert_eq!(eval.context.last_return, 2);
+    }
+
+    #[test]
+    fn failed_redirection_in_pipeline() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+
+        // only the last element's status counts
+        eval.eval_str("true </nonexistent/file | true");
+        assert_eq!(eval.context.last_return, 0);
+        eval.eval_str("true | true </nonexistent/file");
+        assert_eq!(eval.context.last_return, 1);
+        eval.eval_str("true </nonexistent/file");
+        assert_eq!(eval.context.last_return, 1);
+    }
+
+    #[test]
+    fn command_with_default_path() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+        eval.context
+            .env
+            .set_var("PATH", "/nonexistent".to_string(), None);
+
+        eval.eval_str("command -p sh -c 'exit 3'");
+        assert_eq!(eval.context.last_return, 3);
+
+        // a command which is only in PATH isn't found
+        let dir = std::env::temp_dir().join(format!("rash-command-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("only-in-path");
+        std::fs::write(&file, "exit 0\n").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = dir.into_os_string().into_string().unwrap();
+        eval.context.env.set_var("PATH", dir.clone(), None);
+        eval.eval_str("only-in-path");
+        assert_eq!(eval.context.last_return, 0);
+        eval.eval_str("command -p only-in-path");
+        assert_eq!(eval.context.last_return, 127);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn assignments_before_builtins() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+
+        // special builtins keep them
+        eval.eval_str("x=5 export x");
+        assert_eq!(eval.context.env.get("x"), Some("5"));
+        assert!(eval.context.env.exports().contains(&"x=5".to_string()));
+        eval.eval_str("y=1 readonly y; y=2");
+        assert_eq!(eval.context.env.get("y"), Some("1"));
+
+        // other builtins get the old value back
+        eval.eval_str("z=1 true");
+        assert_eq!(eval.context.env.get("z"), None);
+        eval.eval_str("w=1; w=2 test -n x");
+        assert_eq!(eval.context.env.get("w"), Some("1"));
+    }
+
+    #[test]
+    fn lines_are_joined_with_newlines() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+        let mut input = String::new();
+
+        // a comment ends at the end of its line
+        assert!(!eval.eval_line(&mut input, "true && # note"));
+        assert!(eval.eval_line(&mut input, "false"));
+        assert_eq!(eval.context.last_return, 1);
+        input.clear();
+
+        // a newline in quotes is part of the word
+        assert!(!eval.eval_line(&mut input, "test 'a"));
+        assert!(eval.eval_line(&mut input, "b' != ab"));
+        assert_eq!(eval.context.last_return, 0);
+        input.clear();
+
+        assert!(eval.eval_line(&mut input, "&& true"));
+        assert_eq!(eval.context.last_return, 2);
+    }
+
+    #[test]
+    fn stopped_jobs_warning_reset() {
+        let context = context::Context::new(false, environment::empty(), "rash".to_string());
+        let mut eval = Eval::new(context);
+
+        eval.warned_stopped = true;
+        eval.eval_str("true");
+        assert!(!eval.warned_stopped);
     }
 }
@@ -8,6 +8,7 @@
 //
 // http://www.apache.org/licenses/LICENSE-2.0
 
+use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::env;
 use std::ffi::CString;
@@ -15,57 +16,133 @@ use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
 
+use nix::errno::Errno;
+use nix::libc;
 use nix::unistd::*;
 
+use crate::builtins;
 use crate::context;
+use crate::environment;
+use crate::eval;
+use crate::jobs;
+
+/// the search path for commands when PATH is unset or `command -p` is used
+pub const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// Remembers where commands were found so that PATH isn't searched for them every time.
+/// The table empties itself when PATH changes.
+#[derive(Debug, Default)]
+pub struct HashTable {
+    path: String,
+    commands: BTreeMap<String, String>,
+}
+
+impl HashTable {
+    /// Returns the path of a command, searching PATH and remembering the result
+    /// if the command hasn't been found before.
+    pub fn find(&mut self, name: &str, path: &str) -> Option<&str> {
+        if self.path != path {
+            self.path = path.to_string();
+            self.commands.clear();
+        }
+        if !self.commands.contains_key(name) {
+            let found = search_path(name, path)?;
+            self.commands.insert(name.to_string(), found);
+        }
+        self.commands.get(name).map(|p| p.as_str())
+    }
+
+    /// Returns the remembered path of a command if PATH hasn't changed since it was found.
+    pub fn get(&self, name: &str, path: &str) -> Option<&str> {
+        if self.path != path {
+            return None;
+        }
+        self.commands.get(name).map(|p| p.as_str())
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    /// Returns the remembered commands and their paths, sorted by name,
+    /// if PATH hasn't changed since they were found.
+    pub fn iter<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.commands
+            .iter()
+            .filter(move |_| self.path == path)
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Returns the first executable regular file with the given name in a search path.
+pub fn search_path(name: &str, path: &str) -> Option<String> {
+    env::split_paths(path)
+        .map(|dir| filepath(dir, name))
+        .find(|file| {
+            std::fs::metadata(file).is_ok_and(|m| m.is_file())
+                && access(file.as_str(), AccessFlags::X_OK).is_ok()
+        })
+}
 
 /// executes a command and returns the Pid if a child was forked or None if a built-in was called.
+/// When subshell is true, built-ins also run in a forked child so that they can't change
+/// the state of the shell, as for the elements of a multi-command pipeline.
 pub fn run_command(
-    context: &mut context::Context,
+    eval: &mut eval::Eval,
     cmd: &str,
     args: &[String],
     env: &[&str],
-    stdio: context::StdIo,
+    fds: &context::FdTable,
+    group: Option<jobs::ProcessGroup>,
+    subshell: bool,
 ) -> Option<Pid> {
-    let maybe_builtin;
-    {
-        maybe_builtin = context.builtins.get(cmd).copied()
-    }
+    let maybe_builtin = eval.context.builtins.get(cmd).copied();
 
-    if let Some(c) = maybe_builtin {
-        let ret: i32;
-        {
-            ret = c(args, context, stdio);
-        }
-        context.last_return = ret;
-        if stdio.stdin != 0 {
-            close(stdio.stdin).unwrap();
-        }
-        if stdio.stdout != 1 {
-            close(stdio.stdout).unwrap();
+    if let (Some(c), false) = (maybe_builtin, subshell) {
+        // assignments before a builtin are in effect only while it runs,
+        // except for special builtins
+        let saved = eval.context.env.assign_temporarily(env);
+        let ret = c.run(args, eval, fds);
+        if builtins::is_special(cmd) {
+            let export = eval.context.export_flag();
+            eval.context.env.keep(saved, export);
+        } else {
+            eval.context.env.restore(saved);
         }
+        eval.context.last_return = ret;
         return None;
     }
 
+    // look the command up before forking so that the result is remembered for next time
+    if maybe_builtin.is_none() && !cmd.contains('/') {
+        let context = &mut eval.context;
+        let path = context.env.get("PATH").unwrap_or(DEFAULT_PATH);
+        context.hash.find(cmd, path);
+    }
+
     match unsafe { fork() } {
         Ok(ForkResult::Parent { child }) => {
-            if stdio.stdin != 0 {
-                close(stdio.stdin).unwrap();
-            }
-            if stdio.stdout != 1 {
-                close(stdio.stdout).unwrap();
+            if let Some(group) = group {
+                // also set the group from the parent so it is in place before we wait on it
+                let _ = setpgid(child, group.pgid.unwrap_or(child));
             }
             return Some(child);
         }
         Ok(ForkResult::Child) => {
-            dup2(stdio.stdin, 0).expect("could not dup stdin");
-            dup2(stdio.stdout, 1).expect("could not dup stdout");
-            // wire up stdin from last thing in pipeline and exec
-            if let Err(e) = exec(context, cmd, args, env) {
-                println!("could not exec: {}", e);
-                close(stdio.stdin).unwrap();
-                close(stdio.stdout).unwrap();
+            if let Some(group) = group {
+                jobs::enter_process_group(&eval.context, group);
+            }
+            eval.context.traps.reset_for_subshell();
+            if let Err(e) = fds.install() {
+                eprintln!("rash: {}: {}", cmd, e.desc());
+                unsafe { libc::_exit(126) }
+            }
+            if let Some(c) = maybe_builtin {
+                eval.context.env.assign_temporarily(env);
+                std::process::exit(c.run(args, eval, &context::FdTable::new()));
             }
+            let Err(e) = exec(&eval.context, cmd, args, env);
+            exec_failed(cmd, e);
         }
         Err(_) => println!("rash: fork failed"),
     }
@@ -87,12 +164,24 @@ pub fn exec(
         }
     }
 
-    let path = child_env.get("PATH").unwrap_or("/bin:/usr/bin");
+    let path = child_env.get("PATH").unwrap_or(DEFAULT_PATH);
     let exported = child_env.exports();
 
     // if the filename has any slashes in it, don't search the PATH
-    if filename.as_bytes().iter().any(|c| *c == b'/') {
-        return try_exec(filename, args, &exported);
+    if filename.as_bytes().contains(&b'/') {
+        let Err(e) = try_exec(filename, args, &exported);
+        if e == Errno::ENOEXEC {
+            run_script(filename, args, &child_env);
+        }
+        return Err(e);
+    }
+
+    // try where the command was found before. if it has gone, search PATH again.
+    if let Some(hashed) = context.hash.get(filename, path) {
+        let Err(e) = try_exec(hashed, args, &exported);
+        if e == Errno::ENOEXEC {
+            run_script(hashed, args, &child_env);
+        }
     }
 
     // if matching paths are found but none of them can be executed, return the error
@@ -102,16 +191,63 @@ pub fn exec(
     let mut first_error: nix::Error = nix::Error::ENOENT;
 
     for path in env::split_paths(path) {
-        if let Err(e) = try_exec(&filepath(path, filename), args, &exported) {
-            if first_error == nix::Error::ENOENT {
-                first_error = e;
-            }
+        let path = filepath(path, filename);
+        let Err(e) = try_exec(&path, args, &exported);
+        if e == Errno::ENOEXEC {
+            run_script(&path, args, &child_env);
+        }
+        // a missing file or a PATH entry that isn't a directory doesn't count as a match
+        if first_error == nix::Error::ENOENT && e != nix::Error::ENOTDIR {
+            first_error = e;
         }
     }
 
     Err(first_error)
 }
 
+/// Runs a file which isn't in an executable format as a shell script, as POSIX requires.
+/// The current process becomes a fresh shell which keeps only the exported variables
+/// and has the arguments as its positional parameters. Never returns.
+fn run_script(path: &str, args: &[String], env: &environment::Environment) -> ! {
+    let input = match std::fs::read(path) {
+        Ok(input) => String::from_utf8_lossy(&input).into_owned(),
+        Err(e) => {
+            eprintln!("rash: {}: {}", path, e);
+            unsafe { libc::_exit(126) }
+        }
+    };
+
+    let mut exported = environment::empty();
+    for var_eq in env.exports() {
+        exported.set_vareq(&var_eq, Some(true));
+    }
+
+    let mut context = context::Context::new(false, exported, args[0].clone());
+    context.positional = args[1..].to_vec();
+
+    let mut eval = eval::Eval::new(context);
+    let mut lines = input.split_terminator('\n');
+    eval.eval_lines(|_| lines.next().map(str::to_string));
+    eval.run_exit_trap();
+    std::process::exit(eval.context.last_return)
+}
+
+/// Reports why a forked child couldn't exec a command and exits without returning to the shell.
+fn exec_failed(cmd: &str, e: nix::Error) -> ! {
+    let (message, status) = exec_error(e);
+    eprintln!("rash: {}: {}", cmd, message);
+    unsafe { libc::_exit(status) }
+}
+
+/// Describes an error from `exec` along with the exit status it calls for:
+/// 127 if the command wasn't found or 126 if it couldn't be run.
+pub fn exec_error(e: nix::Error) -> (&'static str, i32) {
+    match e {
+        Errno::ENOENT | Errno::ENOTDIR => ("not found", 127),
+        e => (e.desc(), 126),
+    }
+}
+
 fn try_exec(filepath: &str, args: &[String], exported_env: &[String]) -> nix::Result<Infallible> {
     let arg_cstrings = args
         .iter()
@@ -143,3 +279,46 @@ fn filepath(path: PathBuf, filename: &str) -> String {
     // assumes the path is valid UTF-8
     path_buf.into_os_string().into_string().unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn hash_table() {
+        // a directory with a command and a file which can't be run
+        let dir = env::temp_dir().join(format!("rash-hash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, mode) in [("runnable", 0o755), ("plain", 0o644)] {
+            let file = dir.join(file);
+            std::fs::write(&file, "exit 0\n").unwrap();
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let dir = dir.into_os_string().into_string().unwrap();
+        let path = format!("/nonexistent:{}", dir);
+        let found = format!("{}/runnable", dir);
+
+        let mut hash = HashTable::default();
+        assert_eq!(hash.get("runnable", &path), None);
+        assert_eq!(hash.find("runnable", &path), Some(found.as_str()));
+        assert_eq!(hash.find("plain", &path), None);
+        assert_eq!(hash.get("runnable", &path), Some(found.as_str()));
+        assert_eq!(
+            hash.iter(&path).collect::<Vec<_>>(),
+            vec![("runnable", found.as_str())]
+        );
+
+        // the commands found in one PATH don't count for another
+        assert_eq!(hash.get("runnable", DEFAULT_PATH), None);
+        assert_eq!(hash.iter(DEFAULT_PATH).count(), 0);
+        // and searching another PATH forgets them
+        assert_eq!(hash.find("runnable", DEFAULT_PATH), None);
+        assert_eq!(hash.get("runnable", &path), None);
+
+        hash.find("runnable", &path);
+        hash.clear();
+        assert_eq!(hash.get("runnable", &path), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2018 The Rash Project Developers. See the AUTHORS
+// file at the top of this distribution for a list of copyright
+// holders.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Reading input which is shared with other processes.
+//!
+//! The shell and the commands it runs may read from the same descriptor, as in
+//! `while read line; do ...; done < file` or `cmd | { read a; cat; }`. Anything the
+//! shell reads past the end of its line would be lost to the next reader, so lines
+//! are read a byte at a time from pipes and terminals. Seekable files are read in
+//! blocks and the offset is moved back to just after the newline.
+
+use std::os::unix::io::RawFd;
+
+use nix::unistd::{lseek, read, Whence};
+
+/// Reads a line from a descriptor without consuming any input after its newline.
+/// Returns the line without the newline and whether the newline was found.
+/// At end of file, the line holds whatever was read before it, which may be nothing.
+pub fn read_line(fd: RawFd) -> nix::Result<(Vec<u8>, bool)> {
+    let seekable = lseek(fd, 0, Whence::SeekCur).is_ok();
+    let mut line = vec![];
+    let mut buf = [0u8; 512];
+
+    loop {
+        let want = if seekable { buf.len() } else { 1 };
+        let n = read(fd, &mut buf[..want])?;
+        if n == 0 {
+            return Ok((line, false));
+        }
+        if let Some(i) = buf[..n].iter().position(|&b| b == b'\n') {
+            line.extend_from_slice(&buf[..i]);
+            if i + 1 < n {
+                lseek(fd, (i + 1) as i64 - n as i64, Whence::SeekCur)?;
+            }
+            return Ok((line, true));
+        }
+        line.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::os::fd::AsRawFd;
+
+    fn rest(file: &mut File) -> String {
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        rest
+    }
+
+    #[test]
+    fn pipe_is_not_read_past_the_line() {
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        File::from(write_end).write_all(b"one\ntwo\nthree").unwrap();
+
+        let mut input = File::from(read_end);
+        assert_eq!(read_line(input.as_raw_fd()), Ok((b"one".to_vec(), true)));
+        assert_eq!(rest(&mut input), "two\nthree");
+    }
+
+    #[test]
+    fn file_is_left_after_the_line() {
+        let path = std::env::temp_dir().join(format!("rash-input-{}", std::process::id()));
+        let long = "x".repeat(1000);
+        fs::write(&path, format!("first\n{}\nlast", long)).unwrap();
+
+        let mut input = File::open(&path).unwrap();
+        let fd = input.as_raw_fd();
+        assert_eq!(read_line(fd), Ok((b"first".to_vec(), true)));
+        assert_eq!(lseek(fd, 0, Whence::SeekCur), Ok(6));
+        // lines longer than one block are read whole
+        assert_eq!(read_line(fd), Ok((long.clone().into_bytes(), true)));
+        assert_eq!(rest(&mut input), "last");
+
+        // a line without a newline ends at end of file
+        lseek(fd, -4, Whence::SeekEnd).unwrap();
+        assert_eq!(read_line(fd), Ok((b"last".to_vec(), false)));
+        assert_eq!(read_line(fd), Ok((vec![], false)));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1092 @@
+// Copyright 2018 The Rash Project Developers. See the AUTHORS
+// file at the top of this distribution for a list of copyright
+// holders.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Job control: process groups, the job table, and the jobs, fg, and bg builtins.
+
+use std::ffi::CStr;
+use std::os::unix::io::{BorrowedFd, RawFd};
+
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::libc;
+use nix::sys::signal::{kill as send_signal, killpg, signal, SigHandler, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{close, getpgrp, getpid, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+
+use crate::context::Context;
+use crate::context::FdTable;
+use crate::trap;
+
+/// signals the interactive shell ignores so that it can manage the terminal
+pub const JOB_CONTROL_SIGNALS: [Signal; 3] = [Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU];
+
+/// the number of finished background process statuses remembered for `wait`
+const MAX_SAVED_STATUSES: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProcState {
+    Running,
+    Stopped(Signal),
+    Exited(i32),
+    Signaled(Signal, bool),
+}
+
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: Pid,
+    pub state: ProcState,
+}
+
+/// A pipeline of processes which are controlled together
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize,
+    pub pgid: Pid,
+    /// true if the job's processes were put in their own process group
+    pub own_group: bool,
+    pub processes: Vec<Process>,
+    pub command: String,
+    /// terminal settings saved when the job was stopped
+    tmodes: Option<Termios>,
+    /// set when the job changes state in the background until it is reported
+    changed: bool,
+}
+
+impl Job {
+    /// Creates a job from the pids of a pipeline. The first process leads the job's
+    /// process group when `own_group` is set.
+    pub fn new(pids: &[Pid], command: String, own_group: bool) -> Job {
+        Job {
+            id: 0,
+            pgid: pids[0],
+            own_group,
+            processes: pids
+                .iter()
+                .map(|&pid| Process {
+                    pid,
+                    state: ProcState::Running,
+                })
+                .collect(),
+            command,
+            tmodes: None,
+            changed: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.processes.iter().any(|p| p.state == ProcState::Running)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        !self.is_running()
+            && self
+                .processes
+                .iter()
+                .any(|p| matches!(p.state, ProcState::Stopped(_)))
+    }
+
+    pub fn is_done(&self) -> bool {
+        !self.is_running() && !self.is_stopped()
+    }
+
+    /// The status of the last process in the pipeline if it isn't running
+    pub fn status(&self) -> Option<i32> {
+        self.processes.last()?.state.status()
+    }
+
+    /// Describes the state of the job as shown by the jobs builtin
+    pub fn state_name(&self) -> String {
+        if self.is_running() {
+            "Running".to_string()
+        } else if self.is_stopped() {
+            "Stopped".to_string()
+        } else {
+            match self.processes.last().map(|p| p.state) {
+                Some(ProcState::Exited(0)) => "Done".to_string(),
+                Some(ProcState::Exited(r)) => format!("Done({})", r),
+                Some(ProcState::Signaled(sig, core)) => signal_message(sig, core),
+                _ => "Done".to_string(),
+            }
+        }
+    }
+
+    /// Describes how the job was killed by a signal for reporting in an interactive shell.
+    /// Returns an empty message for SIGINT, which the user sent themselves, and None for
+    /// jobs that weren't killed or were killed by SIGPIPE.
+    pub fn termination_message(&self) -> Option<String> {
+        match self.processes.last()?.state {
+            ProcState::Signaled(Signal::SIGINT, _) => Some(String::new()),
+            ProcState::Signaled(Signal::SIGPIPE, _) => None,
+            ProcState::Signaled(sig, core) => Some(signal_message(sig, core)),
+            _ => None,
+        }
+    }
+
+    /// Blocks until every process in the job has terminated or, if `untraced` is set,
+    /// until the job stops.
+    pub fn wait(&mut self, untraced: bool) {
+        let flags = if untraced {
+            Some(WaitPidFlag::WUNTRACED)
+        } else {
+            None
+        };
+        let _ = self.wait_processes(None, flags, false);
+    }
+
+    /// Blocks until the job or one of its processes has terminated.
+    /// Returns EINTR if the wait was interrupted by a signal or a trapped signal arrived
+    /// before it started.
+    pub fn wait_interruptible(&mut self, pid: Option<Pid>) -> nix::Result<()> {
+        self.wait_processes(pid, None, true)
+    }
+
+    fn wait_processes(
+        &mut self,
+        pid: Option<Pid>,
+        flags: Option<WaitPidFlag>,
+        interruptible: bool,
+    ) -> nix::Result<()> {
+        for process in self.processes.iter_mut() {
+            if pid.is_some_and(|pid| pid != process.pid) {
+                continue;
+            }
+            while process.state == ProcState::Running {
+                // a signal may have arrived just as the previous wait returned a status
+                if interruptible && trap::pending_signal().is_some() {
+                    return Err(nix::Error::EINTR);
+                }
+                match waitpid(process.pid, flags) {
+                    Ok(status) => process.update(status),
+                    Err(nix::Error::EINTR) if interruptible => return Err(nix::Error::EINTR),
+                    Err(nix::Error::EINTR) => continue,
+                    Err(e) => {
+                        eprintln!("rash: wait failed: {}", e);
+                        process.state = ProcState::Exited(127);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects state changes of the job's processes without blocking
+    fn poll(&mut self) {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        for process in self.processes.iter_mut() {
+            if matches!(process.state, ProcState::Running | ProcState::Stopped(_)) {
+                if let Ok(status) = waitpid(process.pid, Some(flags)) {
+                    let before = process.state;
+                    process.update(status);
+                    self.changed |= process.state != before;
+                }
+            }
+        }
+    }
+}
+
+impl ProcState {
+    /// The exit status of a process which has exited, stopped, or been killed by a signal
+    pub fn status(&self) -> Option<i32> {
+        match *self {
+            ProcState::Exited(r) => Some(r),
+            ProcState::Stopped(sig) | ProcState::Signaled(sig, _) => Some(128 + sig as i32),
+            ProcState::Running => None,
+        }
+    }
+}
+
+impl Process {
+    fn update(&mut self, status: WaitStatus) {
+        match status {
+            WaitStatus::Exited(_, r) => self.state = ProcState::Exited(r),
+            WaitStatus::Signaled(_, sig, core) => self.state = ProcState::Signaled(sig, core),
+            WaitStatus::Stopped(_, sig) => self.state = ProcState::Stopped(sig),
+            WaitStatus::Continued(_) => self.state = ProcState::Running,
+            _ => {}
+        }
+    }
+}
+
+/// Process group settings for a process forked as part of a job
+#[derive(Debug, Copy, Clone)]
+pub struct ProcessGroup {
+    /// the group to join or None to start a new group
+    pub pgid: Option<Pid>,
+    pub foreground: bool,
+}
+
+/// The descriptor the shell keeps its copy of the terminal on, above the numbers scripts
+/// normally use. A lower one is taken if the descriptor limit doesn't allow it.
+const TERMINAL_FD: RawFd = 255;
+
+/// The terminal owned by an interactive shell
+struct Terminal {
+    fd: RawFd,
+    shell_pgid: Pid,
+    tmodes: Option<Termios>,
+}
+
+impl Terminal {
+    fn fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+/// The job table
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    /// job ids from the most to the least recently used. The first is the current job.
+    recent: Vec<usize>,
+    /// statuses of processes in finished jobs which were removed from the table
+    saved: Vec<(Pid, i32)>,
+    terminal: Option<Terminal>,
+}
+
+impl Jobs {
+    pub fn new() -> Jobs {
+        Default::default()
+    }
+
+    /// Takes control of the terminal on stdin for job control.
+    /// Waits until the shell is in the foreground, puts it in its own process group,
+    /// and ignores the signals that would stop it when it manages the terminal.
+    pub fn init_terminal(&mut self) -> nix::Result<()> {
+        // keep a private copy of the terminal so redirecting stdin doesn't affect it
+        let fd = fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(TERMINAL_FD))
+            .or_else(|_| fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(10)))?;
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+
+        loop {
+            let pgrp = getpgrp();
+            if tcgetpgrp(borrowed)? == pgrp {
+                break;
+            }
+            killpg(pgrp, Signal::SIGTTIN)?;
+        }
+
+        for sig in JOB_CONTROL_SIGNALS {
+            unsafe { signal(sig, SigHandler::SigIgn) }?;
+        }
+
+        let shell_pgid = getpid();
+        // fails harmlessly if the shell is already a process group or session leader
+        let _ = setpgid(shell_pgid, shell_pgid);
+        tcsetpgrp(borrowed, shell_pgid)?;
+
+        self.terminal = Some(Terminal {
+            fd,
+            shell_pgid,
+            tmodes: tcgetattr(borrowed).ok(),
+        });
+        Ok(())
+    }
+
+    pub fn has_terminal(&self) -> bool {
+        self.terminal.is_some()
+    }
+
+    /// Moves the shell's copy of the terminal out of the way when the redirections of
+    /// the exec builtin are about to replace or close its descriptor.
+    pub fn relocate_terminal(&mut self, fds: &FdTable) {
+        let Some(terminal) = &mut self.terminal else {
+            return;
+        };
+        let targets: Vec<RawFd> = fds.descriptors().chain(fds.closed()).collect();
+        if !targets.contains(&terminal.fd) {
+            return;
+        }
+        let base = targets.iter().max().map_or(10, |&fd| fd + 1).max(10);
+        if let Ok(moved) = fcntl(terminal.fd, FcntlArg::F_DUPFD_CLOEXEC(base)) {
+            let _ = close(terminal.fd);
+            terminal.fd = moved;
+        }
+    }
+
+    /// Adds a job to the table, makes it the current job, and returns its id.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let pos = self.jobs.partition_point(|j| j.id < id);
+        self.jobs.insert(pos, job);
+        self.make_current(id);
+        id
+    }
+
+    /// Removes a job from the table and returns it.
+    /// Finished jobs have their process statuses saved for the wait builtin.
+    pub fn take(&mut self, id: usize) -> Option<Job> {
+        let pos = self.jobs.iter().position(|j| j.id == id)?;
+        self.recent.retain(|&r| r != id);
+        let job = self.jobs.remove(pos);
+        if job.is_done() {
+            for p in &job.processes {
+                if let Some(status) = p.state.status() {
+                    self.saved.push((p.pid, status));
+                }
+            }
+            if self.saved.len() > MAX_SAVED_STATUSES {
+                self.saved.drain(..self.saved.len() - MAX_SAVED_STATUSES);
+            }
+        }
+        Some(job)
+    }
+
+    /// Finds the job containing a process
+    pub fn find_pid(&self, pid: Pid) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|j| j.processes.iter().any(|p| p.pid == pid))
+            .map(|j| j.id)
+    }
+
+    /// Removes and returns the saved status of a process from a finished job.
+    pub fn take_saved(&mut self, pid: Pid) -> Option<i32> {
+        let pos = self.saved.iter().rposition(|(p, _)| *p == pid)?;
+        Some(self.saved.remove(pos).1)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    fn make_current(&mut self, id: usize) {
+        self.recent.retain(|&r| r != id);
+        self.recent.insert(0, id);
+    }
+
+    /// Returns '+' for the current job, '-' for the previous job, or ' ' for any other.
+    pub fn mark(&self, id: usize) -> char {
+        match self.recent.iter().position(|&r| r == id) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Finds the id of the job named by a job spec like %%, %+, %-, %n, %string, or %?string.
+    pub fn find(&self, spec: &str) -> Result<usize, String> {
+        let name = match spec.strip_prefix('%') {
+            Some(name) => name,
+            None => return Err(format!("{}: no such job", spec)),
+        };
+
+        let found = match name {
+            "" | "%" | "+" => self.recent.first().copied(),
+            "-" => self.recent.get(1).or(self.recent.first()).copied(),
+            _ => {
+                if let Ok(n) = name.parse::<usize>() {
+                    self.get(n).map(|j| j.id)
+                } else {
+                    let matches: Vec<usize> = match name.strip_prefix('?') {
+                        Some(s) => self
+                            .jobs
+                            .iter()
+                            .filter(|j| j.command.contains(s))
+                            .map(|j| j.id)
+                            .collect(),
+                        None => self
+                            .jobs
+                            .iter()
+                            .filter(|j| j.command.starts_with(name))
+                            .map(|j| j.id)
+                            .collect(),
+                    };
+                    if matches.len() > 1 {
+                        return Err(format!("{}: ambiguous job spec", spec));
+                    }
+                    matches.first().copied()
+                }
+            }
+        };
+
+        found.ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    /// Collects state changes without blocking and describes the jobs which finished or
+    /// stopped since they were last described. Jobs that are done are removed.
+    pub fn take_changes(&mut self) -> Vec<String> {
+        self.update();
+        let changed: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|j| j.changed && !j.is_running())
+            .map(|j| j.id)
+            .collect();
+
+        let mut messages = vec![];
+        for id in changed {
+            let job = self.get(id).unwrap();
+            messages.push(format_job(self, job, false));
+            if job.is_done() {
+                self.take(id);
+            } else if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+                job.changed = false;
+            }
+        }
+        messages
+    }
+
+    /// Collects state changes of every job without blocking.
+    pub fn update(&mut self) {
+        for job in self.jobs.iter_mut() {
+            job.poll();
+        }
+    }
+
+    /// Runs a job in the foreground until it finishes or stops.
+    /// When `cont` is set, the job is sent SIGCONT after it is given the terminal.
+    pub fn foreground(&mut self, job: &mut Job, cont: bool) {
+        let terminal = self.terminal.as_ref().filter(|_| job.own_group);
+
+        if let Some(terminal) = terminal {
+            let _ = tcsetpgrp(terminal.fd(), job.pgid);
+            if cont {
+                if let Some(tmodes) = &job.tmodes {
+                    let _ = tcsetattr(terminal.fd(), SetArg::TCSADRAIN, tmodes);
+                }
+            }
+        }
+
+        if cont {
+            continue_job(job);
+        }
+
+        job.wait(terminal.is_some());
+
+        if let Some(terminal) = terminal {
+            let _ = tcsetpgrp(terminal.fd(), terminal.shell_pgid);
+            if job.is_stopped() {
+                job.tmodes = tcgetattr(terminal.fd()).ok();
+            }
+            if let Some(tmodes) = &terminal.tmodes {
+                let _ = tcsetattr(terminal.fd(), SetArg::TCSADRAIN, tmodes);
+            }
+        }
+    }
+
+    /// Continues a stopped job in the background.
+    pub fn background(&mut self, id: usize) {
+        if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+            continue_job(job);
+        }
+        self.make_current(id);
+    }
+}
+
+/// Sends a signal to every process of a job, continuing a stopped job that is
+/// asked to terminate so that it sees the signal.
+pub fn signal_job(job: &mut Job, sig: Option<Signal>) -> nix::Result<()> {
+    if job.own_group {
+        killpg(job.pgid, sig)?;
+    } else {
+        for p in job.processes.iter().filter(|p| p.state.status().is_none()) {
+            send_signal(p.pid, sig)?;
+        }
+    }
+    if job.is_stopped() && matches!(sig, Some(Signal::SIGTERM) | Some(Signal::SIGHUP)) {
+        continue_job(job);
+    }
+    Ok(())
+}
+
+fn continue_job(job: &mut Job) {
+    if !job.own_group || killpg(job.pgid, Signal::SIGCONT).is_err() {
+        for p in &job.processes {
+            let _ = send_signal(p.pid, Signal::SIGCONT);
+        }
+    }
+    for p in job.processes.iter_mut() {
+        if let ProcState::Stopped(_) = p.state {
+            p.state = ProcState::Running;
+        }
+    }
+}
+
+/// Moves a newly forked child into its job's process group and, for foreground jobs,
+/// gives that group the terminal. Restores the default dispositions of job control signals.
+pub fn enter_process_group(context: &Context, group: ProcessGroup) {
+    let pid = getpid();
+    let pgid = group.pgid.unwrap_or(pid);
+    let _ = setpgid(pid, pgid);
+    if group.foreground {
+        if let Some(terminal) = &context.jobs.terminal {
+            let _ = tcsetpgrp(terminal.fd(), pgid);
+        }
+    }
+    for sig in JOB_CONTROL_SIGNALS {
+        let _ = unsafe { signal(sig, SigHandler::SigDfl) };
+    }
+}
+
+/// Reaps finished background jobs and, in an interactive shell,
+/// reports jobs which finished or stopped since they were last reported.
+/// Reported jobs that are done are removed from the job table. A non-interactive
+/// shell removes finished jobs without reporting them.
+pub fn notify(context: &mut Context) {
+    let changes = context.jobs.take_changes();
+    if context.interactive {
+        for message in changes {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// Describes a signal that terminated a process, like "Segmentation fault (core dumped)"
+pub fn signal_message(sig: Signal, core: bool) -> String {
+    let desc = unsafe { CStr::from_ptr(libc::strsignal(sig as libc::c_int)) }.to_string_lossy();
+    if core {
+        format!("{} (core dumped)", desc)
+    } else {
+        desc.into_owned()
+    }
+}
+
+/// Formats a job the way the jobs builtin lists it
+pub fn format_job(jobs: &Jobs, job: &Job, long: bool) -> String {
+    let pid = if long {
+        format!("{} ", job.pgid)
+    } else {
+        String::new()
+    };
+    format!(
+        "[{}]{} {}{:<24}{}",
+        job.id,
+        jobs.mark(job.id),
+        pid,
+        job.state_name(),
+        job.command
+    )
+}
+
+pub fn jobs(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut long = false;
+    let mut pids_only = false;
+    let mut specs = &args[1..];
+    while let Some(arg) = specs.first() {
+        match arg.as_str() {
+            "-l" => long = true,
+            "-p" => pids_only = true,
+            "--" => {
+                specs = &specs[1..];
+                break;
+            }
+            _ if arg.starts_with('-') => {
+                stdio.eprintln(format_args!("rash: jobs: {}: invalid option", arg));
+                return 2;
+            }
+            _ => break,
+        }
+        specs = &specs[1..];
+    }
+
+    context.jobs.update();
+
+    let ids: Vec<usize> = if specs.is_empty() {
+        context.jobs.iter().map(|j| j.id).collect()
+    } else {
+        let mut ids = vec![];
+        for spec in specs {
+            match context.jobs.find(spec) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    stdio.eprintln(format_args!("rash: jobs: {}", e));
+                    return 1;
+                }
+            }
+        }
+        ids
+    };
+
+    for id in ids {
+        let job = match context.jobs.get(id) {
+            Some(job) => job,
+            None => continue,
+        };
+        if pids_only {
+            stdio.println(format_args!("{}", job.pgid));
+        } else {
+            stdio.println(format_args!("{}", format_job(&context.jobs, job, long)));
+        }
+        // finished jobs are forgotten once they have been reported
+        if job.is_done() {
+            context.jobs.take(id);
+        }
+    }
+
+    0
+}
+
+pub fn fg(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    if !context.jobs.has_terminal() {
+        stdio.eprintln(format_args!("rash: fg: no job control"));
+        return 1;
+    }
+    if args.len() > 2 {
+        stdio.eprintln(format_args!("rash: fg: too many arguments"));
+        return 2;
+    }
+
+    let spec = args.get(1).map(|s| s.as_str()).unwrap_or("%+");
+    let mut job = match context
+        .jobs
+        .find(spec)
+        .map(|id| context.jobs.take(id).unwrap())
+    {
+        Ok(job) => job,
+        Err(e) => {
+            stdio.eprintln(format_args!("rash: fg: {}", e));
+            return 1;
+        }
+    };
+
+    stdio.println(format_args!("{}", job.command));
+    context.jobs.foreground(&mut job, true);
+
+    let status = job.status().unwrap_or(context.last_return);
+    if job.is_stopped() {
+        let id = context.jobs.add(job);
+        let job = context.jobs.get(id).unwrap();
+        stdio.eprintln(format_args!("\n{}", format_job(&context.jobs, job, false)));
+    }
+    status
+}
+
+pub fn bg(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    if !context.jobs.has_terminal() {
+        stdio.eprintln(format_args!("rash: bg: no job control"));
+        return 1;
+    }
+
+    let default = ["%+".to_string()];
+    let specs = if args.len() > 1 { &args[1..] } else { &default };
+    let mut ret = 0;
+
+    for spec in specs {
+        match context.jobs.find(spec) {
+            Ok(id) => {
+                context.jobs.background(id);
+                let job = context.jobs.get(id).unwrap();
+                stdio.println(format_args!(
+                    "[{}]{} {} &",
+                    job.id,
+                    context.jobs.mark(id),
+                    job.command
+                ));
+            }
+            Err(e) => {
+                stdio.eprintln(format_args!("rash: bg: {}", e));
+                ret = 1;
+            }
+        }
+    }
+
+    ret
+}
+
+pub fn wait(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    if args.len() == 1 {
+        let ids: Vec<usize> = context.jobs.iter().map(|j| j.id).collect();
+        for id in ids {
+            if let Err(status) = wait_job(context, id, None) {
+                return status;
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in &args[1..] {
+        let found = if arg.starts_with('%') {
+            context.jobs.find(arg).map(|id| (id, None))
+        } else {
+            match arg.parse::<i32>() {
+                Ok(pid) if pid > 0 => {
+                    let pid = Pid::from_raw(pid);
+                    match context.jobs.find_pid(pid) {
+                        Some(id) => Ok((id, Some(pid))),
+                        None => {
+                            // the process may have already been reaped and reported
+                            status = context.jobs.take_saved(pid).unwrap_or(127);
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    stdio.eprintln(format_args!(
+                        "rash: wait: {}: not a pid or valid job spec",
+                        arg
+                    ));
+                    return 2;
+                }
+            }
+        };
+
+        status = match found {
+            Ok((id, pid)) => match wait_job(context, id, pid) {
+                Ok(status) => status,
+                Err(status) => return status,
+            },
+            Err(e) => {
+                stdio.eprintln(format_args!("rash: wait: {}", e));
+                127
+            }
+        };
+    }
+
+    status
+}
+
+/// Sends a signal, SIGTERM by default, to processes and jobs, or with `-l` lists
+/// signal names or translates between signal names, numbers and exit statuses.
+pub fn kill(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    let mut signo = Signal::SIGTERM as usize;
+
+    match args.first().map(|a| a.as_str()) {
+        Some("-l") => return list_signals(&args[1..], stdio),
+        Some("-s") => {
+            let Some(name) = args.get(1) else {
+                stdio.eprintln(format_args!("rash: kill: -s: option requires an argument"));
+                return 2;
+            };
+            match trap::parse_signal(name) {
+                Some(n) => signo = n,
+                None => {
+                    stdio.eprintln(format_args!("rash: kill: {}: invalid signal", name));
+                    return 1;
+                }
+            }
+            args = &args[2..];
+        }
+        Some("--") => args = &args[1..],
+        Some(arg) if arg.len() > 1 && arg.starts_with('-') => {
+            // -SIG or -number, but a negative number after a signal is a process group
+            match trap::parse_signal(&arg[1..]) {
+                Some(n) => signo = n,
+                None => {
+                    stdio.eprintln(format_args!("rash: kill: {}: invalid signal", &arg[1..]));
+                    return 1;
+                }
+            }
+            args = &args[1..];
+        }
+        _ => {}
+    }
+    if args.first().map(|a| a.as_str()) == Some("--") {
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        stdio.eprintln(format_args!(
+            "rash: kill: usage: kill [-s sig | -sig] pid | %job ... or kill -l [status]"
+        ));
+        return 2;
+    }
+
+    // signal 0 checks that the processes exist without sending anything
+    let sig = Signal::try_from(signo as i32).ok();
+    let mut ret = 0;
+    for arg in args {
+        let result = if arg.starts_with('%') {
+            match context.jobs.find(arg) {
+                Ok(id) => {
+                    let job = context.jobs.jobs.iter_mut().find(|j| j.id == id).unwrap();
+                    signal_job(job, sig)
+                }
+                Err(e) => {
+                    stdio.eprintln(format_args!("rash: kill: {}", e));
+                    ret = 1;
+                    continue;
+                }
+            }
+        } else {
+            match arg.parse::<i32>() {
+                Ok(pid) => send_signal(Pid::from_raw(pid), sig),
+                Err(_) => {
+                    stdio.eprintln(format_args!(
+                        "rash: kill: {}: arguments must be process or job IDs",
+                        arg
+                    ));
+                    ret = 1;
+                    continue;
+                }
+            }
+        };
+        if let Err(e) = result {
+            stdio.eprintln(format_args!("rash: kill: {}: {}", arg, e.desc()));
+            ret = 1;
+        }
+    }
+
+    ret
+}
+
+/// Writes the name of every signal, or for each operand the name of a signal number
+/// or of the signal which terminated a process with that exit status, or the number
+/// of a signal name.
+fn list_signals(args: &[String], stdio: &FdTable) -> i32 {
+    if args.is_empty() {
+        for sig in Signal::iterator() {
+            stdio.println(format_args!("{}", trap::signal_name(sig as usize)));
+        }
+        return 0;
+    }
+
+    let mut ret = 0;
+    for arg in args {
+        match signal_operand(arg) {
+            Ok(out) => stdio.println(format_args!("{}", out)),
+            Err(e) => {
+                stdio.eprintln(format_args!("rash: kill: {}: {}", arg, e));
+                ret = 1;
+            }
+        }
+    }
+    ret
+}
+
+/// Converts an operand of `kill -l`: a signal number or exit status to the signal's name,
+/// or a signal name to its number.
+fn signal_operand(arg: &str) -> Result<String, &'static str> {
+    match arg.parse::<usize>() {
+        Ok(n) => {
+            let signo = if n > 128 { n - 128 } else { n };
+            if signo == 0 || Signal::try_from(signo as i32).is_ok() {
+                Ok(trap::signal_name(signo))
+            } else {
+                Err("invalid signal number or exit status")
+            }
+        }
+        Err(_) => trap::parse_signal(arg)
+            .map(|signo| signo.to_string())
+            .ok_or("invalid signal"),
+    }
+}
+
+/// Waits for a job, or a single process in it, to terminate and returns its status.
+/// Jobs which are done are removed from the job table.
+/// Returns an error status greater than 128 if the wait was interrupted by a signal.
+fn wait_job(context: &mut Context, id: usize, pid: Option<Pid>) -> Result<i32, i32> {
+    let job = match context.jobs.jobs.iter_mut().find(|j| j.id == id) {
+        Some(job) => job,
+        None => return Ok(127),
+    };
+
+    if job.wait_interruptible(pid).is_err() {
+        return Err(128 + trap::pending_signal().unwrap_or(0) as i32);
+    }
+
+    let status = match pid {
+        Some(pid) => job
+            .processes
+            .iter()
+            .find(|p| p.pid == pid)
+            .and_then(|p| p.state.status()),
+        None => job.status(),
+    };
+    if job.is_done() {
+        context.jobs.take(id);
+        if let Some(pid) = pid {
+            context.jobs.take_saved(pid);
+        }
+    }
+    Ok(status.unwrap_or(127))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jobs(commands: &[&str]) -> Jobs {
+        let mut jobs = Jobs::new();
+        for (i, command) in commands.iter().enumerate() {
+            let pid = Pid::from_raw(1000 + i as i32);
+            jobs.add(Job::new(&[pid], command.to_string(), true));
+        }
+        jobs
+    }
+
+    #[test]
+    fn find_job_specs() {
+        let jobs = jobs(&["sleep 10", "vi notes", "sleep 20"]);
+        let cases: &[(&str, Result<usize, &str>)] = &[
+            ("%%", Ok(3)),
+            ("%+", Ok(3)),
+            ("%", Ok(3)),
+            ("%-", Ok(2)),
+            ("%1", Ok(1)),
+            ("%2", Ok(2)),
+            ("%4", Err("%4: no such job")),
+            ("%vi", Ok(2)),
+            ("%sleep", Err("%sleep: ambiguous job spec")),
+            ("%?notes", Ok(2)),
+            ("%?10", Ok(1)),
+            ("%?0", Err("%?0: ambiguous job spec")),
+            ("%?nothing", Err("%?nothing: no such job")),
+            ("%emacs", Err("%emacs: no such job")),
+            ("1", Err("1: no such job")),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(
+                jobs.find(spec),
+                expected.map_err(|e| e.to_string()),
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn find_current_and_previous() {
+        let empty = jobs(&[]);
+        assert!(empty.find("%+").is_err());
+        assert!(empty.find("%-").is_err());
+
+        // with a single job it is both the current and the previous job
+        let single = jobs(&["sleep 10"]);
+        assert_eq!(single.find("%-"), Ok(1));
+
+        // using a job makes it current
+        let mut jobs = jobs(&["a", "b", "c"]);
+        jobs.make_current(1);
+        assert_eq!(jobs.find("%+"), Ok(1));
+        assert_eq!(jobs.find("%-"), Ok(3));
+        assert_eq!(jobs.mark(1), '+');
+        assert_eq!(jobs.mark(3), '-');
+        assert_eq!(jobs.mark(2), ' ');
+    }
+
+    #[test]
+    fn terminal_is_relocated() {
+        let fd = fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(231)).unwrap();
+        let mut jobs = Jobs::new();
+        jobs.terminal = Some(Terminal {
+            fd,
+            shell_pgid: getpid(),
+            tmodes: None,
+        });
+
+        let mut fds = FdTable::new();
+        fds.set(5, 0);
+        jobs.relocate_terminal(&fds);
+        assert_eq!(jobs.terminal.as_ref().unwrap().fd, fd);
+
+        fds.close(fd);
+        jobs.relocate_terminal(&fds);
+        let moved = jobs.terminal.as_ref().unwrap().fd;
+        assert!(moved > fd);
+        assert!(fcntl(fd, FcntlArg::F_GETFD).is_err());
+        assert!(fcntl(moved, FcntlArg::F_GETFD).is_ok());
+        let _ = close(moved);
+    }
+
+    // the wait builtin reaps the process
+    #[allow(clippy::zombie_processes)]
+    fn spawn(status: i32) -> Pid {
+        let child = std::process::Command::new("sh")
+            .args(["-c", &format!("exit {}", status)])
+            .spawn()
+            .unwrap();
+        Pid::from_raw(child.id() as i32)
+    }
+
+    fn run_wait(context: &mut Context, args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        wait(&args, context, &FdTable::quiet())
+    }
+
+    #[test]
+    fn wait_for_processes() {
+        let mut context = Context::new(false, crate::environment::empty(), "rash".to_string());
+
+        let pid = spawn(3);
+        context
+            .jobs
+            .add(Job::new(&[pid], "exit 3".to_string(), false));
+        assert_eq!(run_wait(&mut context, &["wait", &pid.to_string()]), 3);
+        assert!(context.jobs.iter().next().is_none());
+
+        // the status is reported once
+        assert_eq!(run_wait(&mut context, &["wait", &pid.to_string()]), 127);
+
+        let pid = spawn(4);
+        context
+            .jobs
+            .add(Job::new(&[pid], "exit 4".to_string(), false));
+        assert_eq!(run_wait(&mut context, &["wait", "%1"]), 4);
+    }
+
+    #[test]
+    fn wait_for_saved_statuses() {
+        let mut context = Context::new(false, crate::environment::empty(), "rash".to_string());
+
+        let first = spawn(5);
+        let second = spawn(6);
+        context
+            .jobs
+            .add(Job::new(&[first], "exit 5".to_string(), false));
+        context
+            .jobs
+            .add(Job::new(&[second], "exit 6".to_string(), false));
+        // waiting for all jobs returns 0 and keeps their statuses
+        assert_eq!(run_wait(&mut context, &["wait"]), 0);
+        assert!(context.jobs.iter().next().is_none());
+
+        let args = ["wait", &second.to_string(), &first.to_string()];
+        assert_eq!(run_wait(&mut context, &args), 5);
+        assert_eq!(run_wait(&mut context, &["wait", &first.to_string()]), 127);
+    }
+
+    #[test]
+    fn wait_for_unknown_processes() {
+        let mut context = Context::new(false, crate::environment::empty(), "rash".to_string());
+
+        // processes that aren't children of the shell
+        assert_eq!(run_wait(&mut context, &["wait", "1"]), 127);
+        assert_eq!(run_wait(&mut context, &["wait", "%1"]), 127);
+        assert_eq!(run_wait(&mut context, &["wait", "x"]), 2);
+    }
+
+    #[test]
+    fn signal_operands() {
+        let cases: &[(&str, Result<&str, &str>)] = &[
+            ("9", Ok("KILL")),
+            ("15", Ok("TERM")),
+            ("0", Ok("EXIT")),
+            // exit statuses of processes terminated by a signal
+            ("137", Ok("KILL")),
+            ("130", Ok("INT")),
+            ("128", Err("invalid signal number or exit status")),
+            ("200", Err("invalid signal number or exit status")),
+            ("1000", Err("invalid signal number or exit status")),
+            ("KILL", Ok("9")),
+            ("SIGHUP", Ok("1")),
+            ("int", Ok("2")),
+            ("EXIT", Ok("0")),
+            ("-1", Err("invalid signal")),
+            ("NOSUCH", Err("invalid signal")),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(
+                signal_operand(arg),
+                expected.map(|out| out.to_string()),
+                "{}",
+                arg
+            );
+        }
+    }
+}
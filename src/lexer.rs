@@ -1,10 +1,13 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 use std::str::CharIndices;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TokType {
     AssignmentWord,
+    IoNumber,
     Word,
+    Newline,
     Operator,
     EOF,
 }
@@ -50,19 +53,42 @@ impl<'input> fmt::Display for LexError<'input> {
     }
 }
 
+/// Words which are reserved when they appear where a command name is expected
+pub const RESERVED_WORDS: &[&str] = &[
+    "!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for", "if", "in", "then",
+    "until", "while",
+];
+
 pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
 
+static NO_ALIASES: BTreeMap<String, String> = BTreeMap::new();
+
 pub struct Lexer<'input> {
     chars: std::iter::Peekable<CharIndices<'input>>,
     input: &'input str,
     cur_type: TokType,
     cur_start: usize,
     past_first_word: bool,
+    after_redirect: bool,
     next: Option<(usize, char)>,
+    aliases: &'input BTreeMap<String, String>,
+    /// aliases being substituted, which aren't substituted again within themselves
+    expanding: Vec<&'input str>,
+    /// the last word delimited is in a position where an alias may be substituted for it
+    alias_position: bool,
+    /// the previous alias ended with a blank so the next word may be an alias too
+    alias_next: bool,
+    /// tokens from a substituted alias which haven't been returned yet
+    pending: VecDeque<Spanned<Tok<'input>, usize, LexError<'input>>>,
 }
 
 impl<'input> Lexer<'input> {
     pub fn new(input: &'input str) -> Self {
+        Lexer::with_aliases(input, &NO_ALIASES)
+    }
+
+    /// Creates a lexer which substitutes aliases for words where a command name may appear.
+    pub fn with_aliases(input: &'input str, aliases: &'input BTreeMap<String, String>) -> Self {
         let mut chars = input.char_indices().peekable();
         let next = chars.next();
 
@@ -72,10 +98,34 @@ impl<'input> Lexer<'input> {
             cur_type: TokType::EOF,
             cur_start: 0,
             past_first_word: false,
+            after_redirect: false,
             next,
+            aliases,
+            expanding: vec![],
+            alias_position: false,
+            alias_next: false,
+            pending: VecDeque::new(),
         }
     }
 
+    /// Queues the tokens of an alias's value in place of the word which named it.
+    /// The tokens take the location of the word.
+    fn substitute_alias(&mut self, start: usize, name: &'input str, end: usize) {
+        let value: &'input str = &self.aliases[name];
+        let mut lexer = Lexer::with_aliases(value, self.aliases);
+        lexer.expanding = self.expanding.clone();
+        lexer.expanding.push(name);
+
+        for t in lexer.by_ref() {
+            self.pending
+                .push_back(t.map(|(_, tok, _)| (start, tok, end)));
+        }
+        // the words after the alias continue whatever command the alias started
+        self.past_first_word = lexer.past_first_word;
+        self.after_redirect = lexer.after_redirect;
+        self.alias_next = lexer.alias_next || value.ends_with([' ', '\t']);
+    }
+
     fn is_operator_start(c: char) -> bool {
         c == ';' || c == '|' || c == '&' || c == '<' || c == '>'
     }
@@ -105,19 +155,41 @@ impl<'input> Lexer<'input> {
 
         let start = self.cur_start;
 
-        if self.cur_type == TokType::Word && !self.past_first_word {
+        self.alias_position = false;
+        if self.after_redirect {
+            // the file named by a redirection is never an assignment or the command name
+            self.after_redirect = false;
+        } else if self.cur_type == TokType::Word && self.alias_next {
+            self.alias_position = true;
+            self.past_first_word = true;
+        } else if self.cur_type == TokType::Word && !self.past_first_word {
             // check if this word qualifies as an assignment word
             let word = &self.input[start..end];
             let name_idx = word.find('=');
             if name_idx.is_none() || name_idx == Some(0) || !is_name(&word[0..name_idx.unwrap()]) {
                 // once we stop finding assignment words, we're done for good.
                 self.past_first_word = true;
+                self.alias_position = true;
             } else {
                 self.cur_type = TokType::AssignmentWord;
             }
         }
 
-        let t = (start, Tok::new(self.cur_type, &self.input[start..end]), end);
+        if self.cur_type != TokType::Operator {
+            self.alias_next = false;
+        }
+
+        let text = &self.input[start..end];
+        if self.cur_type == TokType::Operator {
+            if text.starts_with('<') || text.starts_with('>') {
+                self.after_redirect = true;
+            } else {
+                // control operators start a new command which may begin with assignments
+                self.past_first_word = false;
+            }
+        }
+
+        let t = (start, Tok::new(self.cur_type, text), end);
 
         // reset token state
         self.cur_start = end;
@@ -131,6 +203,29 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Spanned<Tok<'input>, usize, LexError<'input>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(t) = self.pending.pop_front() {
+                return Some(t);
+            }
+            let t = self.scan()?;
+            let alias_position = std::mem::take(&mut self.alias_position);
+            match t {
+                Ok((start, tok, end))
+                    if alias_position
+                        && self.aliases.contains_key(tok.input)
+                        && !self.expanding.contains(&tok.input) =>
+                {
+                    self.substitute_alias(start, tok.input, end);
+                }
+                t => return Some(t),
+            }
+        }
+    }
+}
+
+impl<'input> Lexer<'input> {
+    /// Scans the next token of the input.
+    fn scan(&mut self) -> Option<Spanned<Tok<'input>, usize, LexError<'input>>> {
         let mut continued = false;
         let mut quoted: Option<char> = None;
         let mut slash_escaped = false;
@@ -154,6 +249,29 @@ impl<'input> Iterator for Lexer<'input> {
                             }
                         }
 
+                        // a word starting with # begins a comment that runs to the end of the line
+                        if c == '#' && self.cur_type == TokType::EOF {
+                            while let Some((_, c)) = self.chars.peek() {
+                                if *c == '\n' {
+                                    break;
+                                }
+                                self.chars.next();
+                            }
+                            continue;
+                        }
+
+                        // unquoted newlines delimit the current token and are a token themselves
+                        if c == '\n' {
+                            if let Some(s) = self.delimit(i) {
+                                // scan the newline again on the next call
+                                return Some(s);
+                            }
+                            self.next = self.chars.next();
+                            self.cur_start = i + 1;
+                            self.past_first_word = false;
+                            return Some(Ok((i, Tok::new(TokType::Newline, "\n"), i + 1)));
+                        }
+
                         // unquoted spaces delimit the current token
                         if c == ' ' || c == '\t' {
                             if let Some(s) = self.delimit(i) {
@@ -198,6 +316,15 @@ impl<'input> Iterator for Lexer<'input> {
                     }
 
                     if Lexer::is_operator_start(c) {
+                        // a number directly before a redirection operator names a file descriptor
+                        if self.cur_type == TokType::Word
+                            && (c == '<' || c == '>')
+                            && self.input[self.cur_start..i]
+                                .chars()
+                                .all(|c| c.is_ascii_digit())
+                        {
+                            self.cur_type = TokType::IoNumber;
+                        }
                         if let Some(s) = self.delimit(i) {
                             self.cur_type = TokType::Operator;
                             return Some(s);
@@ -13,6 +13,8 @@ extern crate lalrpop_util;
 #[macro_use]
 extern crate log;
 
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
 use rustyline::error::ReadlineError;
 use rustyline::history::DefaultHistory;
 
@@ -22,23 +24,67 @@ pub mod context;
 pub mod environment;
 pub mod eval;
 pub mod exec;
+pub mod input;
+pub mod jobs;
 pub mod lexer;
+pub mod printf;
+pub mod resource;
+pub mod test;
+pub mod trap;
 
 lalrpop_mod!(#[allow(clippy::all)] pub grammar);
 
 fn main() {
     pretty_env_logger::init();
 
-    let context = context::Context {
-        interactive: stdin_is_a_tty(),
-        last_return: 0,
-        builtins: builtins::Builtins::new(),
-        env: environment::from_system(),
-    };
+    // the Rust runtime ignores SIGPIPE before main and commands would inherit that,
+    // so writers in a pipeline would see write errors instead of being killed
+    let _ = unsafe { signal::signal(Signal::SIGPIPE, signal::SigHandler::SigDfl) };
+
+    let interactive = stdin_is_a_tty();
+    let arg0 = std::env::args()
+        .next()
+        .unwrap_or_else(|| "rash".to_string());
+    let mut context = context::Context::new(interactive, environment::from_system(), arg0);
+
+    if context.interactive {
+        // an interactive shell survives kill and quit signals while Ctrl-C interrupts
+        // only the foreground command
+        context.traps.ignore_in_shell(Signal::SIGTERM);
+        context.traps.ignore_in_shell(Signal::SIGQUIT);
+        context.traps.catch_in_shell(Signal::SIGINT);
+
+        match context.jobs.init_terminal() {
+            Ok(()) => {
+                context.options.monitor = true;
+                for sig in jobs::JOB_CONTROL_SIGNALS {
+                    context.traps.ignore_in_shell(sig);
+                }
+            }
+            Err(e) => eprintln!("rash: can't access tty; job control turned off: {}", e),
+        }
+    }
 
     let mut eval = eval::Eval::new(context);
 
-    let parser = grammar::programParser::new();
+    if !interactive {
+        // scripts are read without the line editor, which would read ahead on stdin
+        eval.eval_lines(|eval| {
+            jobs::notify(&mut eval.context);
+            match read_script_line() {
+                Ok(line) => Some(line),
+                Err(ReadlineError::Eof) => None,
+                Err(err) => {
+                    println!("rash: error: {:?}", err);
+                    eval.run_exit_trap();
+                    std::process::exit(1)
+                }
+            }
+        });
+        // end of input is the same as the exit builtin
+        let status = eval.context.last_return;
+        eval.exit(status);
+    }
 
     let mut input: String = String::with_capacity(1024);
 
@@ -46,26 +92,41 @@ fn main() {
     let mut rl = rustyline::Editor::<(), DefaultHistory>::new().unwrap();
 
     loop {
+        if prompt_level == 1 {
+            eval.run_traps();
+            jobs::notify(&mut eval.context);
+        }
         let prompt = if prompt_level == 1 { "$ " } else { "> " };
 
-        let readline = rl.readline(prompt);
-        match readline {
-            Ok(line) => input.push_str(line.as_str()),
-            Err(ReadlineError::Interrupted) => std::process::exit(1),
-            Err(ReadlineError::Eof) => std::process::exit(1),
+        let line = match rl.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // discard the partial command and start over at a fresh prompt
+                input.clear();
+                prompt_level = 1;
+                eval.context.last_return = 130;
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                // end of input is the same as the exit builtin
+                eval.exit(eval.context.last_return);
+                input.clear();
+                prompt_level = 1;
+                continue;
+            }
             Err(err) => {
                 println!("rash: error: {:?}", err);
+                eval.run_exit_trap();
                 std::process::exit(1)
             }
-        }
+        };
 
-        if !run_command(&parser, &mut rl, &mut eval, input.as_ref()) {
+        if !eval.eval_line(&mut input, &line) {
             prompt_level = 2;
             continue;
-        } else {
-            prompt_level = 1;
         }
-
+        let _ = rl.add_history_entry(input.trim_end_matches('\n'));
+        prompt_level = 1;
         input.clear();
     }
 }
@@ -74,45 +135,16 @@ fn stdin_is_a_tty() -> bool {
     nix::unistd::isatty(0).unwrap()
 }
 
-/// Parses and runs a command.
-/// Returns false if the input is incomplete.
-fn run_command(
-    parser: &grammar::programParser,
-    rl: &mut rustyline::Editor<(), DefaultHistory>,
-    eval: &mut eval::Eval,
-    input: &str,
-) -> bool {
-    let lexer = lexer::Lexer::new(input);
-    match parser.parse(input, lexer) {
-        Ok(program) => {
-            if eval.context.interactive {
-                let _ = rl.add_history_entry(input);
-            }
-            trace!("{:?}", program);
-            eval.eval(&program);
-            true
+/// Reads the next line of a script from stdin. Nothing past the line is read, so that
+/// commands in the script which read stdin get the input that follows them, as in
+/// `rash < script.sh` or `curl ... | rash`.
+fn read_script_line() -> Result<String, ReadlineError> {
+    loop {
+        match input::read_line(0) {
+            Ok((line, false)) if line.is_empty() => return Err(ReadlineError::Eof),
+            Ok((line, _)) => return Ok(String::from_utf8_lossy(&line).into_owned()),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(ReadlineError::Io(e.into())),
         }
-        Err(e) => match e {
-            lalrpop_util::ParseError::UnrecognizedToken {
-                token: _,
-                expected: _,
-            } => {
-                eprintln!("rash: {}", e);
-                eval.context.last_return = 2;
-                true
-            }
-            lalrpop_util::ParseError::UnrecognizedEof {
-                location: _,
-                expected: _,
-            }
-            | lalrpop_util::ParseError::User {
-                error: lexer::LexError::UnexpectedEOF(_),
-            } => false,
-            _ => {
-                eprintln!("rash: {}", e);
-                eval.context.last_return = 2;
-                true
-            }
-        },
     }
 }
@@ -0,0 +1,557 @@
+// Copyright 2018 The Rash Project Developers. See the AUTHORS
+// file at the top of this distribution for a list of copyright
+// holders.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The echo and printf builtins.
+
+use crate::context::Context;
+use crate::context::FdTable;
+
+/// Writes the arguments separated by spaces and followed by a newline.
+///
+/// POSIX leaves `-n` and backslashes up to the implementation. Like bash and the BSDs,
+/// a first argument of `-n` leaves off the newline and backslash escapes are written
+/// as they are instead of being interpreted as XSI describes. Scripts which need
+/// escapes should use printf.
+pub fn echo(args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    let newline = args.first().map(|a| a.as_str()) != Some("-n");
+    if !newline {
+        args = &args[1..];
+    }
+
+    let mut out = args.join(" ");
+    if newline {
+        out.push('\n');
+    }
+    stdio.write(out.as_bytes());
+    0
+}
+
+/// Writes the arguments according to a format as POSIX specifies, reusing the format
+/// until every argument has been converted.
+pub fn printf(args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    if args.first().map(|a| a.as_str()) == Some("--") {
+        args = &args[1..];
+    }
+    let Some(format) = args.first() else {
+        stdio.eprintln(format_args!(
+            "rash: printf: usage: printf format [argument...]"
+        ));
+        return 2;
+    };
+
+    let mut operands = Operands {
+        args: &args[1..],
+        next: 0,
+        status: 0,
+        stdio,
+    };
+    let mut out = vec![];
+    loop {
+        let start = operands.next;
+        match format_once(format.as_bytes(), &mut operands, &mut out) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                stdio.write(&out);
+                stdio.eprintln(format_args!("rash: printf: {}", e));
+                return 1;
+            }
+        }
+        // the format is reused only while it keeps converting the remaining arguments
+        if operands.next == start || operands.next >= operands.args.len() {
+            break;
+        }
+    }
+
+    stdio.write(&out);
+    operands.status
+}
+
+/// The arguments left to convert. Missing arguments are treated as empty strings or zero.
+struct Operands<'a> {
+    args: &'a [String],
+    next: usize,
+    status: i32,
+    stdio: &'a FdTable,
+}
+
+impl<'a> Operands<'a> {
+    fn string(&mut self) -> &'a str {
+        let arg = self.args.get(self.next).map_or("", |a| a.as_str());
+        self.next += 1;
+        arg
+    }
+
+    /// Converts the next argument to a number. An argument starting with a quote is
+    /// converted to the value of the character after it. Numbers which can't be
+    /// converted completely are reported and set the return value to 1.
+    fn number(&mut self) -> i64 {
+        if self.next >= self.args.len() {
+            return 0;
+        }
+        let arg = self.string();
+        if let Some(quoted) = arg.strip_prefix(['\'', '"']) {
+            return quoted.chars().next().map_or(0, |c| c as i64);
+        }
+
+        let (value, error) = match parse_number(arg.trim_start()) {
+            Ok((value, "")) => (value, None),
+            Ok((value, _)) => (value, Some("invalid number")),
+            Err(value) => (value, Some("arithmetic overflow")),
+        };
+        if let Some(e) = error {
+            self.stdio
+                .eprintln(format_args!("rash: printf: {}: {}", arg, e));
+            self.status = 1;
+        }
+        value
+    }
+}
+
+/// Parses a decimal, octal (leading 0) or hexadecimal (leading 0x) integer with an
+/// optional sign, returning the value and the unparsed rest of the string.
+/// On overflow, returns the value clamped to the range of i64 as an error.
+fn parse_number(s: &str) -> Result<(i64, &str), i64> {
+    let (negative, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (radix, s) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        (16, hex)
+    } else if s.starts_with('0') {
+        (8, s)
+    } else {
+        (10, s)
+    };
+
+    let len = s.find(|c: char| !c.is_digit(radix)).unwrap_or(s.len());
+    let mut value: i64 = 0;
+    for c in s[..len].chars() {
+        let digit = c.to_digit(radix).unwrap() as i64;
+        let next = value.checked_mul(radix as i64).and_then(|v| {
+            if negative {
+                v.checked_sub(digit)
+            } else {
+                v.checked_add(digit)
+            }
+        });
+        match next {
+            Some(v) => value = v,
+            None if negative => return Err(i64::MIN),
+            None => return Err(i64::MAX),
+        }
+    }
+    Ok((value, &s[len..]))
+}
+
+/// The flags, field width and precision of a conversion specification
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pads a converted value with spaces to the field width.
+    fn pad(&self, value: &[u8], out: &mut Vec<u8>) {
+        let fill = self.width.saturating_sub(value.len());
+        if !self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+        out.extend_from_slice(value);
+        if self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+    }
+
+    fn integer(&self, value: i64, conversion: u8, out: &mut Vec<u8>) {
+        let signed = conversion == b'd' || conversion == b'i';
+        let magnitude = if signed {
+            value.unsigned_abs()
+        } else {
+            value as u64
+        };
+        let mut digits = match conversion {
+            b'o' => format!("{:o}", magnitude),
+            b'x' => format!("{:x}", magnitude),
+            b'X' => format!("{:X}", magnitude),
+            _ => magnitude.to_string(),
+        };
+        match self.precision {
+            Some(0) if magnitude == 0 => digits.clear(),
+            Some(p) if digits.len() < p => digits.insert_str(0, &"0".repeat(p - digits.len())),
+            _ => {}
+        }
+
+        let mut prefix = if signed && value < 0 {
+            "-"
+        } else if signed && self.plus {
+            "+"
+        } else if signed && self.space {
+            " "
+        } else {
+            ""
+        };
+        if self.alternate {
+            match conversion {
+                b'o' if !digits.starts_with('0') => digits.insert(0, '0'),
+                b'x' if magnitude != 0 => prefix = "0x",
+                b'X' if magnitude != 0 => prefix = "0X",
+                _ => {}
+            }
+        }
+
+        // zeros go between the sign or prefix and the digits
+        let len = prefix.len() + digits.len();
+        if self.zero && !self.left && self.precision.is_none() && len < self.width {
+            digits.insert_str(0, &"0".repeat(self.width - len));
+        }
+        self.pad(format!("{}{}", prefix, digits).as_bytes(), out);
+    }
+
+    fn string(&self, value: &[u8], out: &mut Vec<u8>) {
+        let len = self.precision.map_or(value.len(), |p| p.min(value.len()));
+        self.pad(&value[..len], out);
+    }
+}
+
+/// Formats the arguments once through the format. Returns false if `\c` ended the output.
+fn format_once(format: &[u8], operands: &mut Operands, out: &mut Vec<u8>) -> Result<bool, String> {
+    let mut i = 0;
+    while i < format.len() {
+        match format[i] {
+            b'\\' => match escape(&format[i + 1..], false) {
+                Escape::Byte(b, len) => {
+                    out.push(b);
+                    i += 1 + len;
+                }
+                Escape::Stop => return Ok(false),
+            },
+            b'%' => {
+                i += 1;
+                if !conversion(format, &mut i, operands, out)? {
+                    return Ok(false);
+                }
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Converts one argument according to the specification starting at format[*i],
+/// just after the `%`. Returns false if `\c` in a `%b` argument ended the output.
+fn conversion(
+    format: &[u8],
+    i: &mut usize,
+    operands: &mut Operands,
+    out: &mut Vec<u8>,
+) -> Result<bool, String> {
+    let start = *i - 1;
+    let mut spec: Spec = Default::default();
+
+    while let Some(&flag) = format.get(*i) {
+        match flag {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alternate = true,
+            b'0' => spec.zero = true,
+            _ => break,
+        }
+        *i += 1;
+    }
+
+    let too_large = |i: &usize, what: &str| {
+        format!(
+            "{}: {} is too large",
+            String::from_utf8_lossy(&format[start..*i]),
+            what
+        )
+    };
+
+    let width = if format.get(*i) == Some(&b'*') {
+        *i += 1;
+        let width = operands.number();
+        spec.left |= width < 0;
+        width.unsigned_abs()
+    } else {
+        digits(format, i)
+    };
+    spec.width = field_size(width).ok_or_else(|| too_large(i, "field width"))?;
+
+    if format.get(*i) == Some(&b'.') {
+        *i += 1;
+        let precision = if format.get(*i) == Some(&b'*') {
+            *i += 1;
+            // a negative precision is taken as if it were left out
+            u64::try_from(operands.number()).ok()
+        } else {
+            Some(digits(format, i))
+        };
+        if let Some(precision) = precision {
+            let precision = field_size(precision).ok_or_else(|| too_large(i, "precision"))?;
+            spec.precision = Some(precision);
+        }
+    }
+
+    let Some(&conversion) = format.get(*i) else {
+        return Err(format!(
+            "{}: missing format character",
+            String::from_utf8_lossy(&format[start..])
+        ));
+    };
+    *i += 1;
+
+    match conversion {
+        b'%' => out.push(b'%'),
+        b'd' | b'i' | b'o' | b'u' | b'x' | b'X' => {
+            spec.integer(operands.number(), conversion, out);
+        }
+        b'c' => {
+            let arg = operands.string();
+            let len = arg.chars().next().map_or(0, |c| c.len_utf8());
+            spec.pad(&arg.as_bytes()[..len], out);
+        }
+        b's' => spec.string(operands.string().as_bytes(), out),
+        b'b' => {
+            let arg = operands.string().as_bytes();
+            let mut value = vec![];
+            let mut j = 0;
+            let mut stop = false;
+            while j < arg.len() {
+                if arg[j] != b'\\' {
+                    value.push(arg[j]);
+                    j += 1;
+                    continue;
+                }
+                match escape(&arg[j + 1..], true) {
+                    Escape::Byte(b, len) => {
+                        value.push(b);
+                        j += 1 + len;
+                    }
+                    Escape::Stop => {
+                        stop = true;
+                        break;
+                    }
+                }
+            }
+            spec.string(&value, out);
+            if stop {
+                return Ok(false);
+            }
+        }
+        _ => {
+            return Err(format!(
+                "{}: invalid directive",
+                String::from_utf8_lossy(&format[start..*i])
+            ))
+        }
+    }
+    Ok(true)
+}
+
+/// Reads a decimal number from format[*i..], returning 0 if there are no digits.
+fn digits(format: &[u8], i: &mut usize) -> u64 {
+    let mut n: u64 = 0;
+    while let Some(d) = format.get(*i).filter(|b| b.is_ascii_digit()) {
+        n = n.saturating_mul(10).saturating_add((d - b'0') as u64);
+        *i += 1;
+    }
+    n
+}
+
+/// Checks that a field width or precision is within the limit C's printf has.
+fn field_size(n: u64) -> Option<usize> {
+    if n > i32::MAX as u64 {
+        None
+    } else {
+        Some(n as usize)
+    }
+}
+
+enum Escape {
+    /// the byte an escape stands for and the number of bytes after the backslash it used
+    Byte(u8, usize),
+    /// `\c`, which ends the output
+    Stop,
+}
+
+/// Interprets the escape sequence following a backslash. Octal escapes are `\ddd`
+/// in a format and `\0ddd` in a `%b` argument. Unknown escapes stand for a backslash.
+fn escape(s: &[u8], in_argument: bool) -> Escape {
+    let byte = match s.first() {
+        Some(b'\\') => b'\\',
+        Some(b'a') => 0x07,
+        Some(b'b') => 0x08,
+        Some(b'f') => 0x0c,
+        Some(b'n') => b'\n',
+        Some(b'r') => b'\r',
+        Some(b't') => b'\t',
+        Some(b'v') => 0x0b,
+        Some(b'c') => return Escape::Stop,
+        Some(b'0'..=b'7') => {
+            let skip = usize::from(in_argument && s[0] == b'0');
+            if in_argument && skip == 0 {
+                return Escape::Byte(b'\\', 0);
+            }
+            let len = s[skip..]
+                .iter()
+                .take(3)
+                .take_while(|b| (b'0'..=b'7').contains(b))
+                .count();
+            let value = s[skip..skip + len]
+                .iter()
+                .fold(0u32, |v, d| v * 8 + (d - b'0') as u32);
+            return Escape::Byte(value as u8, skip + len);
+        }
+        _ => return Escape::Byte(b'\\', 0),
+    };
+    Escape::Byte(byte, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment;
+    use std::fs::File;
+    use std::io::Read;
+
+    /// runs printf with the arguments, returning what it wrote and its status
+    fn run(args: &[&str]) -> (Vec<u8>, i32) {
+        let mut context = Context::new(false, environment::empty(), "rash".to_string());
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        let mut stdio = FdTable::quiet();
+        stdio.set_owned(1, write_end);
+
+        let args: Vec<String> = ["printf"]
+            .iter()
+            .chain(args)
+            .map(|a| a.to_string())
+            .collect();
+        let status = printf(&args, &mut context, &stdio);
+        drop(stdio);
+
+        let mut out = vec![];
+        File::from(read_end).read_to_end(&mut out).unwrap();
+        (out, status)
+    }
+
+    fn output(args: &[&str]) -> String {
+        let (out, status) = run(args);
+        assert_eq!(status, 0, "printf {:?} failed", args);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Ok((42, "")));
+        assert_eq!(parse_number("-0x1f"), Ok((-31, "")));
+        assert_eq!(parse_number("017"), Ok((15, "")));
+        assert_eq!(parse_number("+9z"), Ok((9, "z")));
+        assert_eq!(parse_number("08"), Ok((0, "8")));
+        assert_eq!(parse_number("99999999999999999999"), Err(i64::MAX));
+        assert_eq!(parse_number("-99999999999999999999"), Err(i64::MIN));
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(output(&["%d|%i|%u", "42", "-7", "3"]), "42|-7|3");
+        assert_eq!(output(&["%o|%x|%X", "8", "255", "255"]), "10|ff|FF");
+        assert_eq!(
+            output(&["%#o|%#x|%#X|%#x", "8", "255", "255", "0"]),
+            "010|0xff|0XFF|0"
+        );
+        assert_eq!(
+            output(&["[%5d][%-5d][%05d]", "42", "42", "-42"]),
+            "[   42][42   ][-0042]"
+        );
+        assert_eq!(output(&["[%+d][% d][%+d]", "5", "5", "-5"]), "[+5][ 5][-5]");
+        assert_eq!(
+            output(&["[%.3d][%.0d][%06.3d]", "7", "0", "7"]),
+            "[007][][   007]"
+        );
+        assert_eq!(
+            output(&["[%*d][%-*d][%.*d]", "4", "7", "-3", "7", "2", "7"]),
+            "[   7][7  ][07]"
+        );
+        assert_eq!(output(&["%d %d", "'a", "\"b"]), "97 98");
+        assert_eq!(output(&["%d", "-1"]), "-1");
+        assert_eq!(output(&["%x", "-1"]), "ffffffffffffffff");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            output(&["[%s][%5s][%-5s][%.2s]", "ab", "ab", "ab", "abc"]),
+            "[ab][   ab][ab   ][ab]"
+        );
+        assert_eq!(output(&["[%c][%c]", "xyz", ""]), "[x][]");
+        assert_eq!(output(&["100%%"]), "100%");
+        // missing arguments are empty strings or zero
+        assert_eq!(output(&["[%s][%d]"]), "[][0]");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(output(&["a\\tb\\n"]), "a\tb\n");
+        assert_eq!(output(&["\\101\\0102\\\\"]), "A\u{8}2\\");
+        assert_eq!(output(&["\\q"]), "\\q");
+        // \c in the format ends the output
+        assert_eq!(output(&["a\\cb"]), "a");
+    }
+
+    #[test]
+    fn b_conversion() {
+        assert_eq!(output(&["%b", "a\\tb"]), "a\tb");
+        // octal escapes in %b arguments start with \0
+        assert_eq!(output(&["%b", "\\0101\\101"]), "A\\101");
+        assert_eq!(output(&["[%.2b]", "a\\nb"]), "[a\n]");
+        // \c in a %b argument ends the output, including the rest of the format
+        assert_eq!(output(&["%b|%s\\n", "a\\cb", "x"]), "a");
+    }
+
+    #[test]
+    fn reused_format() {
+        assert_eq!(output(&["%s,", "a", "b", "c"]), "a,b,c,");
+        assert_eq!(output(&["%s=%s\\n", "a", "1", "b"]), "a=1\nb=\n");
+        // a format without conversions is written once
+        assert_eq!(output(&["x\\n", "a", "b"]), "x\n");
+        assert_eq!(output(&["%s\\c,", "a", "b"]), "a");
+    }
+
+    #[test]
+    fn errors() {
+        // bad numbers are converted as far as possible and set the status
+        assert_eq!(run(&["%d|%d", "12ab", "x"]), (b"12|0".to_vec(), 1));
+        assert_eq!(
+            run(&["%d", "99999999999999999999"]),
+            (b"9223372036854775807".to_vec(), 1)
+        );
+        // invalid directives stop the output
+        assert_eq!(run(&["a%yb"]), (b"a".to_vec(), 1));
+        assert_eq!(run(&["a%5"]), (b"a".to_vec(), 1));
+        assert_eq!(run(&["%99999999999999999999d", "1"]), (vec![], 1));
+        assert_eq!(run(&["%.99999999999999999999d", "1"]), (vec![], 1));
+        assert_eq!(run(&["%*d", "99999999999", "1"]), (vec![], 1));
+        assert_eq!(run(&[]), (vec![], 2));
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2018 The Rash Project Developers. See the AUTHORS
+// file at the top of this distribution for a list of copyright
+// holders.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The umask, ulimit and times builtins, which inspect or change the shell process
+//! itself and so can't be separate utilities.
+
+use nix::sys::resource::{
+    getrlimit, getrusage, rlim_t, setrlimit, Resource, UsageWho, RLIM_INFINITY,
+};
+use nix::sys::stat::{umask as set_umask, Mode};
+use nix::sys::time::TimeVal;
+
+use crate::context::Context;
+use crate::context::FdTable;
+
+/// Sets the file mode creation mask from an octal or symbolic mode, or writes the
+/// current mask. `-S` writes it symbolically as the permissions it allows.
+pub fn umask(args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    let symbolic = args.first().map(|a| a.as_str()) == Some("-S");
+    if symbolic {
+        args = &args[1..];
+    }
+
+    // the mask can only be read by setting it
+    let mask = set_umask(Mode::empty());
+    set_umask(mask);
+    let mask = mask.bits() as u32;
+
+    let Some(mode) = args.first() else {
+        if symbolic {
+            let allowed = !mask & 0o777;
+            let class = |shift: u32| {
+                [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')]
+                    .iter()
+                    .filter(|(bit, _)| allowed >> shift & bit != 0)
+                    .map(|(_, c)| *c)
+                    .collect::<String>()
+            };
+            stdio.println(format_args!("u={},g={},o={}", class(6), class(3), class(0)));
+        } else {
+            stdio.println(format_args!("{:04o}", mask));
+        }
+        return 0;
+    };
+
+    let new_mask = if mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        u32::from_str_radix(mode, 8).ok().filter(|&m| m <= 0o777)
+    } else {
+        parse_symbolic(mode, mask)
+    };
+    match new_mask {
+        Some(m) => {
+            set_umask(Mode::from_bits_truncate(m as _));
+            0
+        }
+        None => {
+            stdio.eprintln(format_args!("rash: umask: {}: invalid mode", mode));
+            1
+        }
+    }
+}
+
+/// Applies a symbolic mode like `u=rwx,go-w` to the permissions a mask allows and
+/// returns the resulting mask.
+fn parse_symbolic(mode: &str, mask: u32) -> Option<u32> {
+    let mut allowed = !mask & 0o777;
+
+    for clause in mode.split(',') {
+        let mut chars = clause.chars().peekable();
+        let mut who = 0;
+        while let Some(bits) = chars.peek().and_then(|&c| class_bits(c)) {
+            who |= bits;
+            chars.next();
+        }
+        if who == 0 {
+            who = 0o777;
+        }
+
+        // each clause needs at least one operator
+        let mut op = chars.next()?;
+        loop {
+            if !"+-=".contains(op) {
+                return None;
+            }
+            let mut perms = 0;
+            let mut next = None;
+            for c in chars.by_ref() {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' | 'X' => 0o111,
+                    's' | 't' => 0,
+                    // copy the permissions of another class
+                    'u' => (allowed >> 6 & 7) * 0o111,
+                    'g' => (allowed >> 3 & 7) * 0o111,
+                    'o' => (allowed & 7) * 0o111,
+                    c => {
+                        next = Some(c);
+                        break;
+                    }
+                };
+            }
+            let perms = perms & who;
+            match op {
+                '+' => allowed |= perms,
+                '-' => allowed &= !perms,
+                _ => allowed = allowed & !who | perms,
+            }
+            match next {
+                Some(c) => op = c,
+                None => break,
+            }
+        }
+    }
+
+    Some(!allowed & 0o777)
+}
+
+fn class_bits(c: char) -> Option<u32> {
+    match c {
+        'u' => Some(0o700),
+        'g' => Some(0o070),
+        'o' => Some(0o007),
+        'a' => Some(0o777),
+        _ => None,
+    }
+}
+
+/// The limits ulimit knows with their option letter, description and the unit
+/// their values are given in.
+const LIMITS: &[(char, &str, Resource, rlim_t)] = &[
+    ('c', "core file size (blocks)", Resource::RLIMIT_CORE, 512),
+    ('d', "data seg size (kb)", Resource::RLIMIT_DATA, 1024),
+    ('f', "file size (blocks)", Resource::RLIMIT_FSIZE, 512),
+    ('n', "open files", Resource::RLIMIT_NOFILE, 1),
+    ('s', "stack size (kb)", Resource::RLIMIT_STACK, 1024),
+    ('t', "cpu time (seconds)", Resource::RLIMIT_CPU, 1),
+    ('v', "virtual memory (kb)", Resource::RLIMIT_AS, 1024),
+];
+
+/// Writes or sets a resource limit of the shell, which its children inherit.
+/// `-H` and `-S` choose the hard or soft limit. Both are set when neither is given
+/// and the soft limit is written. `-a` writes every limit. The file size limit
+/// is used when no limit is named.
+pub fn ulimit(args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    let (mut hard, mut soft, mut all) = (false, false, false);
+    let mut limit = &LIMITS[2];
+    let mut args = &args[1..];
+
+    while let Some(arg) = args.first() {
+        if arg == "--" {
+            args = &args[1..];
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            break;
+        }
+        for c in arg[1..].chars() {
+            match c {
+                'H' => hard = true,
+                'S' => soft = true,
+                'a' => all = true,
+                c => match LIMITS.iter().find(|l| l.0 == c) {
+                    Some(l) => limit = l,
+                    None => {
+                        stdio.eprintln(format_args!("rash: ulimit: -{}: invalid option", c));
+                        return 2;
+                    }
+                },
+            }
+        }
+        args = &args[1..];
+    }
+
+    let show = |(_, _, resource, unit): &(char, &str, Resource, rlim_t)| {
+        let (soft_limit, hard_limit) = getrlimit(*resource).unwrap_or((0, 0));
+        format_limit(if hard { hard_limit } else { soft_limit }, *unit)
+    };
+
+    if all {
+        for l in LIMITS {
+            stdio.println(format_args!("{:<26}(-{}) {}", l.1, l.0, show(l)));
+        }
+        return 0;
+    }
+
+    let Some(value) = args.first() else {
+        stdio.println(format_args!("{}", show(limit)));
+        return 0;
+    };
+
+    let (_, _, resource, unit) = *limit;
+    let Some(value) = parse_limit(value, unit) else {
+        stdio.eprintln(format_args!("rash: ulimit: {}: invalid limit", args[0]));
+        return 1;
+    };
+
+    let (mut soft_limit, mut hard_limit) = getrlimit(resource).unwrap_or((0, 0));
+    if hard || !soft {
+        hard_limit = value;
+    }
+    if soft || !hard {
+        soft_limit = value;
+    }
+    match setrlimit(resource, soft_limit, hard_limit) {
+        Ok(()) => 0,
+        Err(e) => {
+            stdio.eprintln(format_args!(
+                "rash: ulimit: error setting limit: {}",
+                e.desc()
+            ));
+            1
+        }
+    }
+}
+
+/// Converts a limit to the unit ulimit writes it in.
+fn format_limit(value: rlim_t, unit: rlim_t) -> String {
+    if value == RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        (value / unit).to_string()
+    }
+}
+
+/// Converts a limit given to ulimit in a unit to the value setrlimit takes.
+fn parse_limit(value: &str, unit: rlim_t) -> Option<rlim_t> {
+    if value == "unlimited" {
+        Some(RLIM_INFINITY)
+    } else {
+        value.parse::<rlim_t>().ok()?.checked_mul(unit)
+    }
+}
+
+/// Writes the user and system CPU time used by the shell and then by its children.
+pub fn times(_args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    for who in [UsageWho::RUSAGE_SELF, UsageWho::RUSAGE_CHILDREN] {
+        match getrusage(who) {
+            Ok(usage) => stdio.println(format_args!(
+                "{} {}",
+                format_time(usage.user_time()),
+                format_time(usage.system_time())
+            )),
+            Err(e) => {
+                stdio.eprintln(format_args!("rash: times: {}", e.desc()));
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn format_time(t: TimeVal) -> String {
+    let millis = t.tv_sec() * 1000 + t.tv_usec() / 1000;
+    format!(
+        "{}m{}.{:03}s",
+        millis / 60000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbolic_modes() {
+        assert_eq!(parse_symbolic("u=rwx,g=rx,o=", 0o000), Some(0o027));
+        assert_eq!(parse_symbolic("g+w", 0o022), Some(0o002));
+        assert_eq!(parse_symbolic("a-w", 0o022), Some(0o222));
+        assert_eq!(parse_symbolic("-w", 0o002), Some(0o222));
+        assert_eq!(parse_symbolic("=r", 0o022), Some(0o333));
+        // the permissions of another class are copied as they are before the clause
+        assert_eq!(parse_symbolic("go=u", 0o077), Some(0o000));
+        assert_eq!(parse_symbolic("go=u", 0o277), Some(0o222));
+        assert_eq!(parse_symbolic("o=g", 0o027), Some(0o022));
+        // several operators in one clause
+        assert_eq!(parse_symbolic("u=rwx-x", 0o777), Some(0o177));
+        assert_eq!(parse_symbolic("o+rwx,o-x", 0o777), Some(0o771));
+        assert_eq!(parse_symbolic("u+s,o+t", 0o022), Some(0o022));
+    }
+
+    #[test]
+    fn invalid_symbolic_modes() {
+        assert_eq!(parse_symbolic("", 0o022), None);
+        assert_eq!(parse_symbolic("u", 0o022), None);
+        assert_eq!(parse_symbolic("urw", 0o022), None);
+        assert_eq!(parse_symbolic("u+z", 0o022), None);
+        assert_eq!(parse_symbolic("u+r,", 0o022), None);
+        assert_eq!(parse_symbolic("x=r", 0o022), None);
+    }
+
+    #[test]
+    fn limit_units() {
+        assert_eq!(parse_limit("10", 1024), Some(10240));
+        assert_eq!(parse_limit("0", 512), Some(0));
+        assert_eq!(parse_limit("unlimited", 512), Some(RLIM_INFINITY));
+        assert_eq!(parse_limit("-1", 1), None);
+        assert_eq!(parse_limit("10k", 1), None);
+        assert_eq!(parse_limit("", 1), None);
+        assert_eq!(parse_limit(&(rlim_t::MAX / 2).to_string(), 512), None);
+
+        assert_eq!(format_limit(10240, 1024), "10");
+        assert_eq!(format_limit(1000, 512), "1");
+        assert_eq!(format_limit(RLIM_INFINITY, 1024), "unlimited");
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2018 The Rash Project Developers. See the AUTHORS
+// file at the top of this distribution for a list of copyright
+// holders.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! The test and `[` builtins.
+//!
+//! Expressions of up to four arguments are evaluated by the rules POSIX gives
+//! for each number of arguments, which keeps them unambiguous. Longer expressions
+//! are parsed with `!`, `-a`, `-o` and parentheses, where `-a` binds tighter than `-o`.
+
+use std::fs;
+use std::fs::Metadata;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use nix::unistd::{access, isatty, AccessFlags};
+
+use crate::context::Context;
+use crate::context::FdTable;
+
+pub fn test(args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    run("test", &args[1..], stdio)
+}
+
+/// `[ expression ]`, which is test with a closing bracket
+pub fn bracket(args: &[String], _context: &mut Context, stdio: &FdTable) -> i32 {
+    match args[1..].split_last() {
+        Some((last, expression)) if last == "]" => run("[", expression, stdio),
+        _ => {
+            stdio.eprintln(format_args!("rash: [: missing ]"));
+            2
+        }
+    }
+}
+
+fn run(name: &str, args: &[String], stdio: &FdTable) -> i32 {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    match evaluate(&args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            stdio.eprintln(format_args!("rash: {}: {}", name, e));
+            2
+        }
+    }
+}
+
+fn evaluate(args: &[&str]) -> Result<bool, String> {
+    match *args {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        ["!", arg] => Ok(arg.is_empty()),
+        [op, arg] if is_unary(op) => unary(op, arg),
+        [op, _] => Err(format!("{}: unary operator expected", op)),
+        [left, op, right] if is_binary(op) => binary(left, op, right),
+        [left, "-a", right] => Ok(!left.is_empty() && !right.is_empty()),
+        [left, "-o", right] => Ok(!left.is_empty() || !right.is_empty()),
+        ["!", ..] if args.len() <= 4 => evaluate(&args[1..]).map(|b| !b),
+        ["(", arg, ")"] => Ok(!arg.is_empty()),
+        [_, op, _] => Err(format!("{}: binary operator expected", op)),
+        ["(", .., ")"] if args.len() == 4 => evaluate(&args[1..3]),
+        _ => {
+            let mut parser = Parser { args, pos: 0 };
+            let result = parser.or()?;
+            match parser.args.get(parser.pos) {
+                Some(extra) => Err(format!("{}: unexpected argument", extra)),
+                None => Ok(result),
+            }
+        }
+    }
+}
+
+/// A parser for expressions of more than four arguments
+struct Parser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let arg = self
+            .peek(0)
+            .ok_or_else(|| "argument expected".to_string())?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            // evaluate both sides so that errors are reported either way
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") {
+            self.pos += 1;
+            return self.not().map(|b| !b);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        match (self.peek(0), self.peek(1), self.peek(2)) {
+            (Some(left), Some(op), Some(_)) if is_binary(op) => {
+                self.pos += 2;
+                let right = self.next()?;
+                binary(left, op, right)
+            }
+            (Some("("), _, _) => {
+                self.pos += 1;
+                let result = self.or()?;
+                match self.next()? {
+                    ")" => Ok(result),
+                    arg => Err(format!("{}: ')' expected", arg)),
+                }
+            }
+            (Some(op), Some(_), _) if is_unary(op) => {
+                self.pos += 1;
+                let arg = self.next()?;
+                unary(op, arg)
+            }
+            _ => Ok(!self.next()?.is_empty()),
+        }
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    matches!(
+        op,
+        "-b" | "-c"
+            | "-d"
+            | "-e"
+            | "-f"
+            | "-g"
+            | "-h"
+            | "-k"
+            | "-L"
+            | "-n"
+            | "-p"
+            | "-r"
+            | "-s"
+            | "-S"
+            | "-t"
+            | "-u"
+            | "-w"
+            | "-x"
+            | "-z"
+    )
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" | "-ef"
+    )
+}
+
+fn unary(op: &str, arg: &str) -> Result<bool, String> {
+    Ok(match op {
+        "-n" => !arg.is_empty(),
+        "-z" => arg.is_empty(),
+        "-b" => is_file(arg, |m| m.file_type().is_block_device()),
+        "-c" => is_file(arg, |m| m.file_type().is_char_device()),
+        "-d" => is_file(arg, |m| m.is_dir()),
+        "-e" => is_file(arg, |_| true),
+        "-f" => is_file(arg, |m| m.is_file()),
+        "-p" => is_file(arg, |m| m.file_type().is_fifo()),
+        "-S" => is_file(arg, |m| m.file_type().is_socket()),
+        "-s" => is_file(arg, |m| m.len() > 0),
+        "-u" => is_file(arg, |m| m.mode() & 0o4000 != 0),
+        "-g" => is_file(arg, |m| m.mode() & 0o2000 != 0),
+        "-k" => is_file(arg, |m| m.mode() & 0o1000 != 0),
+        "-h" | "-L" => fs::symlink_metadata(arg).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => access(arg, AccessFlags::R_OK).is_ok(),
+        "-w" => access(arg, AccessFlags::W_OK).is_ok(),
+        "-x" => access(arg, AccessFlags::X_OK).is_ok(),
+        "-t" => isatty(integer(arg)? as i32).unwrap_or(false),
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    Ok(match op {
+        "=" => left == right,
+        "!=" => left != right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        // a file which exists is newer than one which doesn't
+        "-nt" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(l), Ok(r)) => modified(&l) > modified(&r),
+            (l, _) => l.is_ok(),
+        },
+        "-ot" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(l), Ok(r)) => modified(&l) < modified(&r),
+            (_, r) => r.is_ok(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(l), Ok(r)) => l.dev() == r.dev() && l.ino() == r.ino(),
+            _ => false,
+        },
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+/// Checks the file a path names, following symbolic links. Missing files fail every check.
+fn is_file(path: &str, check: impl Fn(&Metadata) -> bool) -> bool {
+    fs::metadata(path).is_ok_and(|m| check(&m))
+}
+
+fn modified(m: &Metadata) -> (i64, i64) {
+    (m.mtime(), m.mtime_nsec())
+}
+
+fn integer(arg: &str) -> Result<i64, String> {
+    arg.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(args: &str) -> Result<bool, String> {
+        evaluate(
+            &args
+                .split(' ')
+                .filter(|a| !a.is_empty())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn argument_counts() {
+        assert_eq!(eval(""), Ok(false));
+        assert_eq!(evaluate(&[""]), Ok(false));
+        assert_eq!(eval("x"), Ok(true));
+        // operators on their own are strings
+        assert_eq!(eval("-n"), Ok(true));
+        assert_eq!(eval("!"), Ok(true));
+
+        assert_eq!(evaluate(&["!", ""]), Ok(true));
+        assert_eq!(eval("! x"), Ok(false));
+        assert_eq!(eval("-z x"), Ok(false));
+        assert_eq!(evaluate(&["-n", ""]), Ok(false));
+        assert!(eval("x y").is_err());
+
+        assert_eq!(eval("a = a"), Ok(true));
+        assert_eq!(eval("a != a"), Ok(false));
+        assert_eq!(eval("10 -gt 9"), Ok(true));
+        // a binary operator takes precedence over ! and parentheses
+        assert_eq!(eval("! = !"), Ok(true));
+        assert_eq!(eval("( = )"), Ok(false));
+        assert_eq!(eval("! -z x"), Ok(true));
+        assert_eq!(eval("( x )"), Ok(true));
+        assert_eq!(eval("a -a b"), Ok(true));
+        assert_eq!(evaluate(&["a", "-a", ""]), Ok(false));
+        assert_eq!(evaluate(&["", "-o", "b"]), Ok(true));
+        assert!(eval("a b c").is_err());
+
+        assert_eq!(eval("! a = b"), Ok(true));
+        assert_eq!(eval("! ! -n x"), Ok(true));
+        assert_eq!(eval("( -n x )"), Ok(true));
+        assert_eq!(eval("( a = b )"), Ok(false));
+        assert_eq!(eval("-n a -o -z"), Ok(true));
+    }
+
+    #[test]
+    fn precedence() {
+        // -a binds tighter than -o
+        assert_eq!(eval("x -o a = b -a a = b"), Ok(true));
+        assert_eq!(eval("a = b -a a = b -o x"), Ok(true));
+        assert_eq!(eval("a = b -o x -a a = b"), Ok(false));
+        // ! binds tighter than -a
+        assert_eq!(eval("! a = b -a a = a"), Ok(true));
+        assert_eq!(eval("! a = a -a a = a"), Ok(false));
+        assert_eq!(eval("! ! a = a -a x"), Ok(true));
+        // parentheses group
+        assert_eq!(eval("( x -o a = b ) -a a = b"), Ok(false));
+        assert_eq!(eval("! ( a = a -o a = b )"), Ok(false));
+        assert_eq!(eval("( ( a = a ) )"), Ok(true));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval("1 -eq x").is_err());
+        assert!(eval("-t x").is_err());
+        assert!(eval("( a = a").is_err());
+        assert!(eval("( a = a b").is_err());
+        assert!(eval("a = a -a").is_err());
+        assert!(eval("a = a b c").is_err());
+        // errors are reported even when the result doesn't depend on them
+        assert!(eval("a = a -o 1 -eq x").is_err());
+
+        let stdio = FdTable::quiet();
+        let args = |s: &str| s.split(' ').map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(run("test", &args("a = a"), &stdio), 0);
+        assert_eq!(run("test", &args("a = b"), &stdio), 1);
+        assert_eq!(run("test", &args("1 -lt x"), &stdio), 2);
+        assert_eq!(run("test", &args("a b c d e"), &stdio), 2);
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright 2018 The Rash Project Developers. See the AUTHORS
+// file at the top of this distribution for a list of copyright
+// holders.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+
+//! Signal dispositions and the trap builtin.
+//!
+//! Trapped signals are caught by a handler which only records that the signal arrived.
+//! The evaluator runs the trap actions of pending signals between commands.
+
+use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nix::libc;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+use crate::context::Context;
+use crate::context::FdTable;
+
+/// one more than the highest signal number, with 0 standing for the EXIT pseudo-signal
+const NSIG: usize = 65;
+
+static PENDING: [AtomicBool; NSIG] = [const { AtomicBool::new(false) }; NSIG];
+static ANY_PENDING: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(signo: libc::c_int) {
+    if let Some(pending) = PENDING.get(signo as usize) {
+        pending.store(true, Ordering::SeqCst);
+        ANY_PENDING.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Ignore,
+    Command(String),
+}
+
+/// The trap actions set by the user, indexed by signal number
+#[derive(Debug, Clone)]
+pub struct Traps {
+    actions: Vec<Option<Action>>,
+    /// signals which were ignored when a non-interactive shell started and can't be trapped
+    ignored_on_entry: Vec<bool>,
+    /// dispositions the interactive shell uses for itself when no trap is set
+    shell_handlers: Vec<Option<SigHandler>>,
+}
+
+impl Default for Traps {
+    fn default() -> Self {
+        Traps {
+            actions: vec![None; NSIG],
+            ignored_on_entry: vec![false; NSIG],
+            shell_handlers: vec![None; NSIG],
+        }
+    }
+}
+
+impl Traps {
+    /// Records the signals ignored on entry to the shell.
+    pub fn new(interactive: bool) -> Traps {
+        let mut traps: Traps = Default::default();
+        if !interactive {
+            for sig in Signal::iterator() {
+                traps.ignored_on_entry[sig as usize] = is_ignored(sig);
+            }
+        }
+        traps
+    }
+
+    pub fn get(&self, signo: usize) -> Option<&Action> {
+        self.actions.get(signo)?.as_ref()
+    }
+
+    /// Sets or, when action is None, resets the trap for a signal
+    /// and changes the signal's disposition to match.
+    pub fn set(&mut self, signo: usize, action: Option<Action>) -> nix::Result<()> {
+        if self.ignored_on_entry[signo] {
+            return Ok(());
+        }
+        if let Ok(sig) = Signal::try_from(signo as i32) {
+            let handler = match action {
+                None => self.shell_handlers[signo].unwrap_or(SigHandler::SigDfl),
+                Some(Action::Ignore) => SigHandler::SigIgn,
+                Some(Action::Command(_)) => SigHandler::Handler(on_signal),
+            };
+            // no SA_RESTART so that trapped signals interrupt the wait builtin
+            let act = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+            unsafe { sigaction(sig, &act) }?;
+        }
+        self.actions[signo] = action;
+        Ok(())
+    }
+
+    /// Ignores a signal in the shell itself without affecting the commands it runs.
+    pub fn ignore_in_shell(&mut self, sig: Signal) {
+        self.set_shell_handler(sig, SigHandler::SigIgn);
+    }
+
+    /// Catches a signal in the shell itself so that it doesn't terminate the shell
+    /// while leaving the default disposition in the commands it runs.
+    pub fn catch_in_shell(&mut self, sig: Signal) {
+        self.set_shell_handler(sig, SigHandler::Handler(on_signal));
+    }
+
+    fn set_shell_handler(&mut self, sig: Signal, handler: SigHandler) {
+        let signo = sig as usize;
+        self.shell_handlers[signo] = Some(handler);
+        if self.actions[signo].is_none() {
+            let _ = self.set(signo, None);
+        }
+    }
+
+    /// Removes the trap commands in a subshell or forked child.
+    /// Caught signals and signals ignored only by the shell go back to their default
+    /// disposition while signals ignored with a trap stay ignored.
+    pub fn reset_for_subshell(&mut self) {
+        for (signo, pending) in PENDING.iter().enumerate() {
+            if self.shell_handlers[signo].take().is_some() && self.actions[signo].is_none() {
+                let _ = self.set(signo, None);
+            }
+            match self.actions[signo] {
+                Some(Action::Command(_)) => {
+                    let _ = self.set(signo, None);
+                }
+                Some(Action::Ignore) => {
+                    let _ = self.set(signo, Some(Action::Ignore));
+                }
+                None => {}
+            }
+            pending.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Goes back to traps saved before reset_for_subshell when the shell carries on after all.
+    pub fn restore(&mut self, saved: Traps) {
+        *self = saved;
+        for signo in 1..NSIG {
+            let action = self.actions[signo].clone();
+            let _ = self.set(signo, action);
+        }
+    }
+
+    /// Removes the EXIT trap action, returning it so that it can be run.
+    pub fn take_exit(&mut self) -> Option<String> {
+        match self.actions[0].take() {
+            Some(Action::Command(cmd)) => Some(cmd),
+            _ => None,
+        }
+    }
+}
+
+fn is_ignored(sig: Signal) -> bool {
+    let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::sigaction(sig as libc::c_int, ptr::null(), &mut old) };
+    ret == 0 && old.sa_sigaction == libc::SIG_IGN
+}
+
+/// Takes the signals which arrived since the last call, in signal number order.
+pub fn take_pending() -> Vec<usize> {
+    if !ANY_PENDING.swap(false, Ordering::SeqCst) {
+        return vec![];
+    }
+    (1..NSIG)
+        .filter(|&signo| PENDING[signo].swap(false, Ordering::SeqCst))
+        .collect()
+}
+
+/// Returns the lowest numbered signal which has arrived but hasn't been handled.
+pub fn pending_signal() -> Option<usize> {
+    (1..NSIG).find(|&signo| PENDING[signo].load(Ordering::SeqCst))
+}
+
+/// Parses a signal name like INT or SIGINT, a signal number, or EXIT.
+/// Returns the signal number with 0 for EXIT.
+pub fn parse_signal(name: &str) -> Option<usize> {
+    if let Ok(n) = name.parse::<usize>() {
+        return if n == 0 || Signal::try_from(n as i32).is_ok() {
+            Some(n)
+        } else {
+            None
+        };
+    }
+    let upper = name.to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    if bare == "EXIT" {
+        return Some(0);
+    }
+    Signal::from_str(&format!("SIG{}", bare))
+        .ok()
+        .map(|sig| sig as usize)
+}
+
+/// Returns the name of a signal without the SIG prefix or EXIT for 0.
+pub fn signal_name(signo: usize) -> String {
+    match Signal::try_from(signo as i32) {
+        Ok(sig) => sig.as_str()[3..].to_string(),
+        Err(_) if signo == 0 => "EXIT".to_string(),
+        Err(_) => signo.to_string(),
+    }
+}
+
+pub fn trap(args: &[String], context: &mut Context, stdio: &FdTable) -> i32 {
+    let mut args = &args[1..];
+    if args.first().map(|a| a.as_str()) == Some("--") {
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        for signo in 0..NSIG {
+            let cmd = match context.traps.get(signo) {
+                Some(Action::Command(cmd)) => cmd.as_str(),
+                Some(Action::Ignore) => "",
+                None => continue,
+            };
+            stdio.println(format_args!(
+                "trap -- {} {}",
+                crate::builtins::quote(cmd),
+                signal_name(signo)
+            ));
+        }
+        return 0;
+    }
+
+    // a lone condition or a first operand that is a number resets the listed conditions
+    let (action, conditions) = if args.len() == 1 || args[0].parse::<usize>().is_ok() {
+        (None, args)
+    } else {
+        let action = match args[0].as_str() {
+            "-" => None,
+            "" => Some(Action::Ignore),
+            cmd => Some(Action::Command(cmd.to_string())),
+        };
+        (action, &args[1..])
+    };
+
+    let mut ret = 0;
+    for condition in conditions {
+        match parse_signal(condition) {
+            // SIGKILL and SIGSTOP can't be caught or ignored
+            Some(signo)
+                if signo == Signal::SIGKILL as usize || signo == Signal::SIGSTOP as usize =>
+            {
+                stdio.eprintln(format_args!(
+                    "rash: trap: {}: cannot trap this signal",
+                    condition
+                ));
+                ret = 1;
+            }
+            Some(signo) => {
+                if let Err(e) = context.traps.set(signo, action.clone()) {
+                    stdio.eprintln(format_args!("rash: trap: {}: {}", condition, e.desc()));
+                    ret = 1;
+                }
+            }
+            None => {
+                stdio.eprintln(format_args!("rash: trap: {}: bad trap", condition));
+                ret = 1;
+            }
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_names() {
+        let cases: &[(&str, Option<usize>)] = &[
+            ("INT", Some(2)),
+            ("SIGINT", Some(2)),
+            ("int", Some(2)),
+            ("sigterm", Some(15)),
+            ("2", Some(2)),
+            ("0", Some(0)),
+            ("EXIT", Some(0)),
+            ("KILL", Some(9)),
+            ("NOSUCH", None),
+            ("SIG", None),
+            ("", None),
+            ("-1", None),
+            ("1000", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(parse_signal(name), *expected, "{}", name);
+        }
+
+        assert_eq!(signal_name(0), "EXIT");
+        assert_eq!(signal_name(2), "INT");
+        assert_eq!(signal_name(Signal::SIGUSR1 as usize), "USR1");
+        assert_eq!(signal_name(1000), "1000");
+
+        // names round trip through their numbers
+        for sig in Signal::iterator() {
+            assert_eq!(parse_signal(&signal_name(sig as usize)), Some(sig as usize));
+        }
+    }
+}